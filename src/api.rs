@@ -0,0 +1,257 @@
+use crate::files::{ensure_directory, write_file_contents, Error as FilesError};
+use crate::navigation::post_site_path;
+use crate::parsing::{BlockSource, Entry, Metadata, Page, Post};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Couldn't serialize api document '{name}'")]
+    SerializeError {
+        source: serde_json::Error,
+        name: String,
+    },
+
+    #[error("Couldn't write api document '{name}'")]
+    WriteError { source: FilesError, name: String },
+}
+
+static API_DIRECTORY: &str = "api";
+
+#[derive(Serialize)]
+struct ApiEntry<'a> {
+    name: &'a str,
+    kind: &'a str,
+    title: &'a str,
+    url: Option<String>,
+    api_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<&'a Metadata>,
+    /// An entry's fields, with their placeholders resolved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<&'a serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct ApiDocument<'a> {
+    #[serde(flatten)]
+    entry: ApiEntry<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    html: Option<&'a str>,
+    blocks: &'a serde_json::Value,
+    sources: &'a [BlockSource],
+}
+
+#[derive(Serialize)]
+struct ApiIndex<'a> {
+    posts: Vec<ApiEntry<'a>>,
+    pages: Vec<ApiEntry<'a>>,
+    entries: Vec<ApiEntry<'a>>,
+}
+
+/// Writes the content api: `api/index.json` listing every post, page and
+/// entry, and one document per item with its rendered html (or an entry's
+/// fields) and source blocks.
+///
+/// Urls are site-scoped, like those registered with the renderer. `html`
+/// holds each post and page's content, and `fields` each entry's fields, with
+/// their placeholders already resolved.
+pub(crate) fn export_api(
+    output_dir: &String,
+    posts: &HashMap<String, Post>,
+    pages: &HashMap<String, Page>,
+    entries: &HashMap<String, Entry>,
+    page_urls: &HashMap<String, String>,
+    html: &HashMap<String, String>,
+    fields: &HashMap<String, serde_json::Value>,
+) -> Result<(), Error> {
+    let mut index = ApiIndex {
+        posts: Vec::new(),
+        pages: Vec::new(),
+        entries: Vec::new(),
+    };
+
+    for (name, post) in posts {
+        let entry = || ApiEntry {
+            name,
+            kind: "post",
+            title: &post.title,
+            url: Some(post_site_path(post)),
+            api_url: api_site_path(name),
+            metadata: Some(&post.metadata),
+            fields: None,
+        };
+        let document = ApiDocument {
+            entry: entry(),
+            html: Some(html.get(name).unwrap_or(&post.html)),
+            blocks: &post.blocks,
+            sources: &post.sources,
+        };
+        write_document(output_dir, name, &document)?;
+        index.posts.push(entry());
+    }
+
+    for (name, page) in pages {
+        let entry = || ApiEntry {
+            name,
+            kind: "page",
+            title: &page.title,
+            url: page_urls.get(name).cloned(),
+            api_url: api_site_path(name),
+            metadata: Some(&page.metadata),
+            fields: None,
+        };
+        let document = ApiDocument {
+            entry: entry(),
+            html: Some(html.get(name).unwrap_or(&page.html)),
+            blocks: &page.blocks,
+            sources: &page.sources,
+        };
+        write_document(output_dir, name, &document)?;
+        index.pages.push(entry());
+    }
+
+    for (name, item) in entries {
+        let entry = || ApiEntry {
+            name,
+            kind: &item.content_type,
+            title: item.title(),
+            url: item.url.clone(),
+            api_url: api_site_path(name),
+            metadata: None,
+            fields: fields.get(name),
+        };
+        let document = ApiDocument {
+            entry: entry(),
+            html: None,
+            blocks: &item.blocks,
+            sources: &item.sources,
+        };
+        write_document(output_dir, name, &document)?;
+        index.entries.push(entry());
+    }
+
+    // newest first, then by name so the output is stable
+    index.posts.sort_by(|a, b| {
+        let published = |entry: &ApiEntry| entry.metadata.map(|m| m.published_date.clone());
+        (published(b), a.name).cmp(&(published(a), b.name))
+    });
+    index.pages.sort_by(|a, b| a.name.cmp(b.name));
+    index
+        .entries
+        .sort_by(|a, b| (a.kind, a.name).cmp(&(b.kind, b.name)));
+
+    let path = format!("{}/{}/index.json", output_dir, API_DIRECTORY);
+    write_json(&path, "index", &index)
+}
+
+/// Replaces every string in `value` with the output of `resolve`.
+pub(crate) fn resolve_strings<E>(
+    value: &mut serde_json::Value,
+    resolve: &mut impl FnMut(&str) -> Result<String, E>,
+) -> Result<(), E> {
+    match value {
+        serde_json::Value::String(s) => *s = resolve(s)?,
+        serde_json::Value::Array(values) => {
+            for value in values {
+                resolve_strings(value, resolve)?;
+            }
+        }
+        serde_json::Value::Object(fields) => {
+            for value in fields.values_mut() {
+                resolve_strings(value, resolve)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The site-scoped path of a content item's api document. The content name
+/// keeps its extension, so `index.page` can't overwrite the api index and
+/// `foo.post` and `foo.page` get documents of their own.
+pub(crate) fn api_site_path(name: &str) -> String {
+    format!("{}/{}.json", API_DIRECTORY, name)
+}
+
+fn write_document(output_dir: &String, name: &str, document: &ApiDocument) -> Result<(), Error> {
+    let path = format!("{}/{}", output_dir, api_site_path(name));
+    write_json(&path, name, document)
+}
+
+fn write_json(path: &String, name: &str, value: &impl Serialize) -> Result<(), Error> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| Error::SerializeError {
+        source: e,
+        name: String::from(name),
+    })?;
+    if let Some(directory) = Path::new(path).parent() {
+        ensure_directory(directory).map_err(|e| Error::WriteError {
+            source: e,
+            name: String::from(name),
+        })?;
+    }
+    write_file_contents(&json, path).map_err(|e| Error::WriteError {
+        source: e,
+        name: String::from(name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{api_site_path, export_api};
+    use crate::parsing::{Page, Post};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_api_documents_keep_the_content_extension() {
+        assert_eq!(api_site_path("posts/foo.post"), "api/posts/foo.post.json");
+        assert_ne!(api_site_path("foo.post"), api_site_path("foo.page"));
+    }
+
+    #[test]
+    fn test_index_page_does_not_replace_the_api_index() {
+        let metadata = json!({ "author_name": "author", "published_date": "2022-01-01" });
+        let posts: HashMap<String, Post> = HashMap::from([(
+            String::from("index.post"),
+            Post::try_from(json!({
+                "metadata": metadata,
+                "title": "Post",
+                "slug": "index",
+                "content": "",
+            }))
+            .unwrap(),
+        )]);
+        let pages: HashMap<String, Page> = HashMap::from([(
+            String::from("index.page"),
+            Page::try_from(json!({ "metadata": metadata, "title": "Home", "content": "" }))
+                .unwrap(),
+        )]);
+        let output = std::env::temp_dir().join(format!("site-builder-api-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&output);
+        let output_dir = output.to_string_lossy().to_string();
+
+        export_api(
+            &output_dir,
+            &posts,
+            &pages,
+            &HashMap::new(),
+            &HashMap::new(),
+            &HashMap::new(),
+            &HashMap::new(),
+        )
+        .unwrap();
+        let read = |path: &str| -> serde_json::Value {
+            serde_json::from_str(&std::fs::read_to_string(output.join(path)).unwrap()).unwrap()
+        };
+
+        let index = read("api/index.json");
+        assert_eq!(index["pages"][0]["api_url"], "api/index.page.json");
+        assert_eq!(index["posts"][0]["api_url"], "api/index.post.json");
+        assert_eq!(read("api/index.page.json")["title"], "Home");
+        assert_eq!(read("api/index.post.json")["title"], "Post");
+        let _ = std::fs::remove_dir_all(&output);
+    }
+}
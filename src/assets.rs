@@ -0,0 +1,355 @@
+use crate::files::{get_paths_from_glob, get_stripped_base_path_string, Error as FilesError};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use blake2s_simd::Params;
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Couldn't find assets to fingerprint")]
+    AssetSearchError { source: FilesError },
+
+    #[error("Couldn't read asset '{path}'")]
+    AssetReadError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Couldn't write asset '{path}'")]
+    AssetWriteError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Couldn't rename asset '{path}'")]
+    AssetRenameError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Couldn't remove stale asset '{path}'")]
+    StaleAssetRemoveError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Assets reference each other in a cycle: {chain}")]
+    AssetCycleError { chain: String },
+}
+
+// an 8 byte hash, base64 encoded without padding
+static HASH_LENGTH: usize = 11;
+
+/// Maps every asset copied from `source_dir` to `{output_dir}/{site_dir}` to
+/// the site-scoped path it's served from, for templates to look up as
+/// `assets["css/site.css"]`.
+///
+/// When `fingerprint` is set, the copies are renamed to carry a hash of their
+/// content (`css/site.<hash>.css`) so they can be cached indefinitely. The
+/// `@import` and `url()` references between stylesheets and the assets they
+/// use are rewritten to the renamed copies first, so a change to any asset
+/// also changes the hash of the stylesheets that reference it. Fingerprinted
+/// copies left by earlier builds are removed.
+pub(crate) fn collect_assets(
+    source_dir: &String,
+    output_dir: &String,
+    site_dir: &str,
+    fingerprint: bool,
+) -> Result<HashMap<String, String>, Error> {
+    let pattern = format!("{}/**/*.*", source_dir);
+    let paths = get_paths_from_glob(&pattern).map_err(|e| Error::AssetSearchError { source: e })?;
+    let names: HashSet<String> = paths
+        .iter()
+        .filter(|path| path.is_file())
+        .filter_map(|path| get_stripped_base_path_string(path, source_dir).ok())
+        .collect();
+
+    let mut fingerprinter = Fingerprinter {
+        output_dir,
+        site_dir,
+        names: &names,
+        fingerprinted: HashMap::new(),
+        visiting: Vec::new(),
+    };
+    let mut assets = HashMap::new();
+    for name in &names {
+        let site_path = format!("{}/{}", site_dir, name);
+        let served_path = match fingerprint {
+            true => fingerprinter.fingerprint(name)?,
+            false => site_path.clone(),
+        };
+        remove_stale_copies(output_dir, site_dir, name, &served_path, &names)?;
+        assets.insert(site_path, served_path);
+    }
+
+    Ok(assets)
+}
+
+struct Fingerprinter<'a> {
+    output_dir: &'a str,
+    site_dir: &'a str,
+    names: &'a HashSet<String>,
+    /// Asset names, and the site path of their renamed copy.
+    fingerprinted: HashMap<String, String>,
+    visiting: Vec<String>,
+}
+impl Fingerprinter<'_> {
+    fn fingerprint(&mut self, name: &str) -> Result<String, Error> {
+        if let Some(fingerprinted) = self.fingerprinted.get(name) {
+            return Ok(fingerprinted.clone());
+        }
+        if self.visiting.iter().any(|visiting| visiting == name) {
+            let mut chain = self.visiting.clone();
+            chain.push(String::from(name));
+            return Err(Error::AssetCycleError {
+                chain: chain.join(" -> "),
+            });
+        }
+
+        let site_path = format!("{}/{}", self.site_dir, name);
+        let output_path = format!("{}/{}", self.output_dir, site_path);
+        let mut bytes = std::fs::read(&output_path).map_err(|e| Error::AssetReadError {
+            source: e,
+            path: output_path.clone(),
+        })?;
+
+        // stylesheets are fingerprinted once what they reference has been
+        if is_stylesheet(name) {
+            self.visiting.push(String::from(name));
+            let css = String::from_utf8_lossy(&bytes).into_owned();
+            let mut rewritten = String::new();
+            let mut rest = 0;
+            for (range, reference) in css_references(&css) {
+                let target = match resolve_reference(name, reference) {
+                    Some(target) if self.names.contains(&target) => target,
+                    _ => continue,
+                };
+                let fingerprinted = self.fingerprint(&target)?;
+                rewritten.push_str(&css[rest..range.start]);
+                rewritten.push_str(&with_file_name(reference, &fingerprinted));
+                rest = range.end;
+            }
+            rewritten.push_str(&css[rest..]);
+            self.visiting.pop();
+
+            if rewritten != css {
+                bytes = rewritten.into_bytes();
+                std::fs::write(&output_path, &bytes).map_err(|e| Error::AssetWriteError {
+                    source: e,
+                    path: output_path.clone(),
+                })?;
+            }
+        }
+
+        let hash = Params::new().hash_length(8).hash(&bytes);
+        let hash = Base64UrlUnpadded::encode_string(hash.as_bytes());
+        let fingerprinted = match site_path.rsplit_once('.') {
+            Some((stem, extension)) if !stem.ends_with('/') => {
+                format!("{}.{}.{}", stem, hash, extension)
+            }
+            _ => format!("{}.{}", site_path, hash),
+        };
+        let fingerprinted_path = format!("{}/{}", self.output_dir, fingerprinted);
+        std::fs::rename(&output_path, &fingerprinted_path).map_err(|e| {
+            Error::AssetRenameError {
+                source: e,
+                path: output_path.clone(),
+            }
+        })?;
+        self.fingerprinted
+            .insert(String::from(name), fingerprinted.clone());
+
+        Ok(fingerprinted)
+    }
+}
+
+fn is_stylesheet(name: &str) -> bool {
+    name.rsplit_once('.')
+        .is_some_and(|(_, extension)| extension.eq_ignore_ascii_case("css"))
+}
+
+/// The byte range and text of every `url()` and `@import` reference in `css`.
+fn css_references(css: &str) -> Vec<(std::ops::Range<usize>, &str)> {
+    let mut references = Vec::new();
+    let quoted = |start: usize| {
+        let quote = css[start..].chars().next()?;
+        if quote != '"' && quote != '\'' {
+            return None;
+        }
+        let end = css[start + 1..].find(quote)? + start + 1;
+        Some((start + 1)..end)
+    };
+
+    for (start, _) in css.match_indices("url(") {
+        let start = start + 4;
+        let start = start + (css[start..].len() - css[start..].trim_start().len());
+        let range = match quoted(start) {
+            Some(range) => range,
+            None => match css[start..].find(')') {
+                Some(end) => start..start + css[start..start + end].trim_end().len(),
+                None => continue,
+            },
+        };
+        references.push((range.clone(), &css[range]));
+    }
+    for (start, _) in css.match_indices("@import") {
+        let start = start + 7;
+        let start = start + (css[start..].len() - css[start..].trim_start().len());
+        // `@import url(...)` was found above
+        if let Some(range) = quoted(start) {
+            references.push((range.clone(), &css[range]));
+        }
+    }
+
+    references.sort_by_key(|(range, _)| range.start);
+    references
+}
+
+/// The asset name a reference from the asset `from` points at, if it's a
+/// relative path that stays among the assets.
+fn resolve_reference(from: &str, reference: &str) -> Option<String> {
+    let path = reference.split(['?', '#']).next()?;
+    let is_external = path.starts_with('/')
+        || path
+            .split('/')
+            .next()
+            .is_some_and(|first| first.contains(':'));
+    if path.is_empty() || is_external {
+        return None;
+    }
+
+    let mut segments: Vec<&str> = from.split('/').collect();
+    segments.pop();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// `reference` with its file name swapped for the file name of `site_path`,
+/// which sits in the same directory.
+fn with_file_name(reference: &str, site_path: &str) -> String {
+    let end = reference.find(['?', '#']).unwrap_or(reference.len());
+    let (path, suffix) = reference.split_at(end);
+    let file_name = site_path.rsplit('/').next().unwrap_or(site_path);
+    match path.rsplit_once('/') {
+        Some((directory, _)) => format!("{}/{}{}", directory, file_name, suffix),
+        None => format!("{}{}", file_name, suffix),
+    }
+}
+
+/// Removes copies of the asset `name` fingerprinted by earlier builds, keeping
+/// `served_path` and the copies of other assets whose names look fingerprinted
+/// (`fonts.inter-latin.css` next to `fonts.css`).
+fn remove_stale_copies(
+    output_dir: &str,
+    site_dir: &str,
+    name: &str,
+    served_path: &str,
+    names: &HashSet<String>,
+) -> Result<(), Error> {
+    let site_path = format!("{}/{}", site_dir, name);
+    let (stem, extension) = match site_path.rsplit_once('.') {
+        Some((stem, extension)) if !stem.ends_with('/') => (stem, extension),
+        _ => return Ok(()),
+    };
+    let prefix = format!("{}/{}.", output_dir, stem);
+    let pattern = format!("{}*.{}", glob::Pattern::escape(&prefix), extension);
+    let paths = get_paths_from_glob(&pattern).map_err(|e| Error::AssetSearchError { source: e })?;
+    for path in paths {
+        let path_string = path.to_string_lossy().to_string();
+        let hash = path_string
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_suffix(&format!(".{}", extension)));
+        let is_fingerprinted = hash.is_some_and(|hash| {
+            hash.len() == HASH_LENGTH
+                && hash
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        });
+        let is_asset = path_string
+            .strip_prefix(&format!("{}/{}/", output_dir, site_dir))
+            .is_some_and(|name| names.contains(name));
+        let is_served = path_string == format!("{}/{}", output_dir, served_path);
+        if !is_fingerprinted || is_asset || is_served {
+            continue;
+        }
+        std::fs::remove_file(&path).map_err(|e| Error::StaleAssetRemoveError {
+            source: e,
+            path: path_string,
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect_assets, css_references, resolve_reference, with_file_name};
+
+    #[test]
+    fn test_css_references_resolve_among_assets() {
+        let css = "@import \"base.css\";\n@import url(fonts.css);\n\
+            body { background: url( '../img/bg.png?v=1' ); }\n\
+            a { background: url(data:image/png;base64,AAAA); }";
+        let references: Vec<&str> = css_references(css).into_iter().map(|(_, r)| r).collect();
+        assert_eq!(
+            references,
+            [
+                "base.css",
+                "fonts.css",
+                "../img/bg.png?v=1",
+                "data:image/png;base64,AAAA"
+            ]
+        );
+
+        assert_eq!(
+            resolve_reference("theme/site.css", "../img/bg.png?v=1").as_deref(),
+            Some("img/bg.png")
+        );
+        assert_eq!(resolve_reference("site.css", "../outside.css"), None);
+        assert_eq!(
+            resolve_reference("site.css", "data:image/png;base64,AAAA"),
+            None
+        );
+        assert_eq!(
+            with_file_name("../img/bg.png?v=1", "css/img/bg.abc.png"),
+            "../img/bg.abc.png?v=1"
+        );
+        assert_eq!(
+            with_file_name("base.css", "css/base.abc.css"),
+            "base.abc.css"
+        );
+    }
+
+    #[test]
+    fn test_stale_copies_spare_assets_that_look_fingerprinted() {
+        let root = std::env::temp_dir().join(format!("site-builder-assets-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let (source, output) = (root.join("source"), root.join("output"));
+        std::fs::create_dir_all(source.join("css")).unwrap();
+        std::fs::create_dir_all(output.join("static/css")).unwrap();
+        std::fs::write(source.join("css/fonts.css"), "a{}").unwrap();
+        std::fs::write(source.join("css/fonts.inter-latin.css"), "b{}").unwrap();
+        std::fs::write(output.join("static/css/fonts.inter-latin.css"), "b{}").unwrap();
+        std::fs::write(output.join("static/css/fonts.abcdefghijk.css"), "old").unwrap();
+
+        let assets = collect_assets(
+            &source.to_string_lossy().to_string(),
+            &output.to_string_lossy().to_string(),
+            "static",
+            false,
+        )
+        .unwrap();
+        assert_eq!(assets["static/css/fonts.css"], "static/css/fonts.css");
+        assert!(output.join("static/css/fonts.inter-latin.css").exists());
+        assert!(!output.join("static/css/fonts.abcdefghijk.css").exists());
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,276 @@
+use crate::files::{
+    ensure_directory, get_paths_from_glob, get_stripped_base_path_string, Error as FilesError,
+};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use blake2s_simd::Params;
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Couldn't find output files to compress")]
+    CompressSearchError { source: FilesError },
+
+    #[error("Couldn't read '{path}'")]
+    CompressReadError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Couldn't compress '{path}'")]
+    CompressEncodeError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Couldn't write '{path}'")]
+    CompressWriteError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Couldn't create cache directory '{path}'")]
+    CacheDirectoryError { source: FilesError, path: String },
+
+    #[error("Couldn't write compression manifest '{path}'")]
+    ManifestWriteError {
+        source: serde_json::Error,
+        path: String,
+    },
+}
+
+static MANIFEST_FILENAME: &str = "precompressed.json";
+static EXTENSIONS: [&str; 4] = ["html", "css", "xml", "json"];
+static ENCODINGS: [(&str, Encode); 2] = [("gz", gzip), ("br", brotli)];
+static DEFAULT_MIN_SIZE: u64 = 1024;
+static BROTLI_QUALITY: u32 = 11;
+static BROTLI_WINDOW: u32 = 22;
+
+/// The `compression` section of config.json.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub(crate) struct CompressionConfig {
+    pub min_size: Option<u64>,
+    pub gzip: Option<bool>,
+    pub brotli: Option<bool>,
+}
+
+type Encode = fn(&[u8]) -> std::io::Result<Vec<u8>>;
+
+/// Writes `.gz` and `.br` siblings for every html, css, xml and json file in
+/// `output_dir`, for hosts that serve precompressed files.
+///
+/// Files smaller than `min_size` are skipped, and siblings are removed when
+/// their file is skipped, their encoding is turned off or their file is gone.
+/// The content hash of each compressed file is kept in a manifest in
+/// `cache_dir`, outside the published output, so siblings are only
+/// regenerated when their source changes.
+pub(crate) fn precompress_output(
+    output_dir: &String,
+    cache_dir: &String,
+    config: &CompressionConfig,
+    log: bool,
+) -> Result<(), Error> {
+    let min_size = config.min_size.unwrap_or(DEFAULT_MIN_SIZE);
+    let (encodings, disabled): (Vec<_>, Vec<_>) =
+        ENCODINGS
+            .iter()
+            .partition(|(extension, _)| match *extension {
+                "gz" => config.gzip.unwrap_or(true),
+                _ => config.brotli.unwrap_or(true),
+            });
+
+    ensure_directory(cache_dir).map_err(|e| Error::CacheDirectoryError {
+        source: e,
+        path: cache_dir.clone(),
+    })?;
+    let manifest_path = format!("{}/{}", cache_dir, MANIFEST_FILENAME);
+    let previous: HashMap<String, String> = std::fs::read(&manifest_path)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default();
+    let mut manifest = HashMap::new();
+
+    let mut paths = Vec::new();
+    for extension in EXTENSIONS {
+        let pattern = format!("{}/**/*.{}", output_dir, extension);
+        paths.append(
+            &mut get_paths_from_glob(&pattern)
+                .map_err(|e| Error::CompressSearchError { source: e })?,
+        );
+    }
+
+    for path in paths {
+        let name = match get_stripped_base_path_string(&path, output_dir) {
+            Ok(name) => name,
+            _ => continue,
+        };
+        let path = path.to_string_lossy().to_string();
+        for (extension, _) in &disabled {
+            remove_sibling(&path, extension);
+        }
+        let bytes = std::fs::read(&path).map_err(|e| Error::CompressReadError {
+            source: e,
+            path: path.clone(),
+        })?;
+
+        if (bytes.len() as u64) < min_size {
+            for (extension, _) in &encodings {
+                remove_sibling(&path, extension);
+            }
+            continue;
+        }
+
+        let hash = Params::new().hash_length(16).hash(&bytes);
+        let hash = Base64UrlUnpadded::encode_string(hash.as_bytes());
+        for (extension, encode) in &encodings {
+            let variant_path = format!("{}.{}", path, extension);
+            if previous.get(&name) == Some(&hash) && Path::new(&variant_path).exists() {
+                continue;
+            }
+            let encoded = encode(&bytes).map_err(|e| Error::CompressEncodeError {
+                source: e,
+                path: path.clone(),
+            })?;
+            if log {
+                println!(
+                    "compressed {} -> .{} ({} -> {} bytes)",
+                    name,
+                    extension,
+                    bytes.len(),
+                    encoded.len()
+                );
+            }
+            std::fs::write(&variant_path, encoded).map_err(|e| Error::CompressWriteError {
+                source: e,
+                path: variant_path.clone(),
+            })?;
+        }
+        manifest.insert(name, hash);
+    }
+
+    // siblings of files that are no longer exported
+    for (extension, _) in ENCODINGS {
+        let pattern = format!("{}/**/*.{}", output_dir, extension);
+        let siblings =
+            get_paths_from_glob(&pattern).map_err(|e| Error::CompressSearchError { source: e })?;
+        for sibling in siblings {
+            let source = sibling.with_extension("");
+            let compressible = source
+                .extension()
+                .is_some_and(|extension| EXTENSIONS.iter().any(|e| extension == *e));
+            if compressible && !source.exists() {
+                remove_sibling(&source.to_string_lossy(), extension);
+            }
+        }
+    }
+
+    let json = serde_json::to_vec_pretty(&manifest).map_err(|e| Error::ManifestWriteError {
+        source: e,
+        path: manifest_path.clone(),
+    })?;
+    std::fs::write(&manifest_path, json).map_err(|e| Error::CompressWriteError {
+        source: e,
+        path: manifest_path.clone(),
+    })
+}
+
+fn remove_sibling(path: &str, extension: &str) {
+    // a missing sibling is what we want anyway
+    let _ = std::fs::remove_file(format!("{}.{}", path, extension));
+}
+
+fn gzip(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+fn brotli(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    {
+        let mut encoder =
+            brotli::CompressorWriter::new(&mut encoded, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+        encoder.write_all(bytes)?;
+        encoder.flush()?;
+    }
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{precompress_output, CompressionConfig};
+    use std::path::{Path, PathBuf};
+
+    /// A fresh output and cache directory under the system temp directory.
+    fn directories(name: &str) -> (PathBuf, String, String) {
+        let root =
+            std::env::temp_dir().join(format!("site-builder-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let output = root.join("output");
+        std::fs::create_dir_all(&output).unwrap();
+        let output_dir = output.to_string_lossy().to_string();
+        let cache_dir = root.join("cache").to_string_lossy().to_string();
+        (root, output_dir, cache_dir)
+    }
+
+    fn config(min_size: u64) -> CompressionConfig {
+        CompressionConfig {
+            min_size: Some(min_size),
+            ..CompressionConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_unchanged_files_are_not_recompressed() {
+        let (root, output_dir, cache_dir) = directories("compress-skip");
+        let page = Path::new(&output_dir).join("index.html");
+        let gz = Path::new(&output_dir).join("index.html.gz");
+        std::fs::write(&page, "<p>hello</p>").unwrap();
+        precompress_output(&output_dir, &cache_dir, &config(0), false).unwrap();
+        assert!(gz.exists());
+        assert!(!Path::new(&output_dir).join("precompressed.json").exists());
+
+        // an unchanged hash keeps the existing sibling
+        std::fs::write(&gz, "marker").unwrap();
+        precompress_output(&output_dir, &cache_dir, &config(0), false).unwrap();
+        assert_eq!(std::fs::read(&gz).unwrap(), b"marker");
+
+        // a changed file is compressed again
+        std::fs::write(&page, "<p>hello again</p>").unwrap();
+        precompress_output(&output_dir, &cache_dir, &config(0), false).unwrap();
+        assert_ne!(std::fs::read(&gz).unwrap(), b"marker");
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_stale_siblings_are_removed() {
+        let (root, output_dir, cache_dir) = directories("compress-stale");
+        let output = Path::new(&output_dir);
+        std::fs::write(output.join("small.css"), "a{}").unwrap();
+        std::fs::write(output.join("small.css.gz"), "old").unwrap();
+        std::fs::write(output.join("gone.html.br"), "old").unwrap();
+        std::fs::write(output.join("archive.tar.gz"), "kept").unwrap();
+        std::fs::write(output.join("big.json"), "[0000000000]").unwrap();
+        std::fs::write(output.join("big.json.br"), "old").unwrap();
+        let config = CompressionConfig {
+            brotli: Some(false),
+            ..config(8)
+        };
+
+        precompress_output(&output_dir, &cache_dir, &config, false).unwrap();
+        // below the minimum size
+        assert!(!output.join("small.css.gz").exists());
+        // its file is no longer exported
+        assert!(!output.join("gone.html.br").exists());
+        // not a sibling of a compressible file
+        assert!(output.join("archive.tar.gz").exists());
+        // its encoding is turned off
+        assert!(!output.join("big.json.br").exists());
+        assert!(output.join("big.json.gz").exists());
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::files::{
+    get_paths_from_glob, get_stripped_base_path_string, read_file_contents, Error as FilesError,
+};
+use crate::parsing::toml_to_json;
+use serde_json::{Map, Value};
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Couldn't find data files")]
+    DataSearchError { source: FilesError },
+
+    #[error("Couldn't read data file '{path}'")]
+    DataReadError { source: FilesError, path: String },
+
+    #[error("Couldn't parse data file '{path}': {reason}")]
+    DataParseError { reason: String, path: String },
+
+    #[error("Data file '{path}' collides with another data file or directory")]
+    DataConflictError { path: String },
+}
+
+/// Loads every JSON, TOML and CSV file under `data_dir` into one object,
+/// keyed by path: `data/links/friends.toml` becomes `links.friends`.
+///
+/// CSV files become an array with an object per row, keyed by the header.
+pub(crate) fn load_data(data_dir: &String) -> Result<Value, Error> {
+    let mut data = Value::Object(Map::new());
+    if !Path::new(data_dir).is_dir() {
+        return Ok(data);
+    }
+
+    let pattern = format!("{}/**/*.*", data_dir);
+    let paths = get_paths_from_glob(&pattern).map_err(|e| Error::DataSearchError { source: e })?;
+    for path in paths {
+        let name = match get_stripped_base_path_string(&path, data_dir) {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let (key, extension) = match name.rsplit_once('.') {
+            Some((key, extension @ ("json" | "toml" | "csv"))) => (key, extension),
+            _ => continue,
+        };
+        let contents = read_file_contents(&path).map_err(|e| Error::DataReadError {
+            source: e,
+            path: name.clone(),
+        })?;
+        let value = parse_data(extension, &contents).map_err(|reason| Error::DataParseError {
+            reason,
+            path: name.clone(),
+        })?;
+        insert_data(&mut data, key, value)
+            .map_err(|_| Error::DataConflictError { path: name.clone() })?;
+    }
+
+    Ok(data)
+}
+
+fn parse_data(extension: &str, contents: &str) -> Result<Value, String> {
+    match extension {
+        "json" => serde_json::from_str(contents).map_err(|e| e.to_string()),
+        "toml" => toml::from_str::<toml::Value>(contents)
+            .map(toml_to_json)
+            .map_err(|e| e.to_string()),
+        "csv" => {
+            let mut reader = csv::Reader::from_reader(contents.as_bytes());
+            let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+            let mut rows = Vec::new();
+            for record in reader.records() {
+                let record = record.map_err(|e| e.to_string())?;
+                let row = headers
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(header, field)| (String::from(header), Value::from(field)))
+                    .collect();
+                rows.push(Value::Object(row));
+            }
+            Ok(Value::Array(rows))
+        }
+        _ => Err(format!("'{}' isn't a data format", extension)),
+    }
+}
+
+/// Inserts `value` at the `/`-separated `key`, creating objects along the way.
+/// Fails if that would replace anything but an object with another object.
+fn insert_data(data: &mut Value, key: &str, value: Value) -> Result<(), ()> {
+    let mut node = data;
+    let mut components = key.split('/').peekable();
+    while let Some(component) = components.next() {
+        let object = node.as_object_mut().ok_or(())?;
+        if components.peek().is_none() {
+            return match (object.get_mut(component), value) {
+                (None, value) => {
+                    object.insert(String::from(component), value);
+                    Ok(())
+                }
+                (Some(Value::Object(existing)), Value::Object(value)) => {
+                    for (key, value) in value {
+                        if existing.contains_key(&key) {
+                            return Err(());
+                        }
+                        existing.insert(key, value);
+                    }
+                    Ok(())
+                }
+                _ => Err(()),
+            };
+        }
+        node = object
+            .entry(component)
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{insert_data, parse_data};
+    use serde_json::json;
+
+    #[test]
+    fn test_data_is_keyed_by_path() {
+        let mut data = json!({});
+        let friends = parse_data("csv", "name,url\nAda,https://a.example\n").unwrap();
+        let reading = parse_data("toml", "[[books]]\ntitle = \"Dune\"\n").unwrap();
+        insert_data(&mut data, "links/friends", friends).unwrap();
+        insert_data(&mut data, "reading", reading).unwrap();
+        assert_eq!(
+            data,
+            json!({
+                "links": { "friends": [{ "name": "Ada", "url": "https://a.example" }] },
+                "reading": { "books": [{ "title": "Dune" }] }
+            })
+        );
+        assert!(insert_data(&mut data, "links/friends/more", json!(1)).is_err());
+    }
+}
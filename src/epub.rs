@@ -0,0 +1,793 @@
+use crate::feeds::{unix_seconds, utc_timestamp};
+use crate::files::{ensure_directory, Error as FilesError};
+use crate::navigation::post_site_path;
+use crate::parsing::{Post, SiteContext};
+use pulldown_cmark::escape::escape_html;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Section '{name}' doesn't exist")]
+    UnknownSectionError { name: String },
+
+    #[error("'{name}' has no posts to bundle")]
+    EmptyBookError { name: String },
+
+    #[error("Couldn't read image '{path}'")]
+    ImageReadError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Couldn't create epub directory for '{path}'")]
+    DirectoryError { source: FilesError, path: String },
+
+    #[error("Couldn't write epub '{path}'")]
+    WriteError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Couldn't archive epub '{path}'")]
+    ArchiveError {
+        source: zip::result::ZipError,
+        path: String,
+    },
+}
+
+/// The base url chapters are rendered against: chapters live in
+/// `OEBPS/text/`, so site-scoped urls land one directory up.
+pub(crate) static CHAPTER_BASE_URL: &str = "..";
+static IMAGES_PREFIX: &str = "../images/";
+static VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Which posts go into a book.
+pub(crate) enum EpubSelection {
+    Section(String),
+    Series(String),
+}
+
+/// The posts of a section or series, in reading order.
+pub(crate) struct Book<'a> {
+    pub name: String,
+    pub title: String,
+    pub posts: Vec<(&'a String, &'a Post)>,
+}
+
+impl<'a> Book<'a> {
+    /// A filesystem-friendly name for the book.
+    pub(crate) fn file_stem(&self) -> String {
+        self.name
+            .trim_matches('/')
+            .replace(|c: char| c.is_whitespace() || c == '/', "-")
+    }
+}
+
+struct Heading {
+    level: usize,
+    id: String,
+    text: String,
+}
+
+struct Chapter {
+    file: String,
+    title: String,
+    xhtml: String,
+    headings: Vec<Heading>,
+}
+
+/// Collects the posts of a section (by its directory), ordered chronologically,
+/// or of a series, ordered by `series_order`.
+pub(crate) fn collect_book<'a>(
+    context: &'a SiteContext,
+    posts: &'a HashMap<String, Post>,
+    selection: &EpubSelection,
+) -> Result<Book<'a>, Error> {
+    let (name, mut posts): (&String, Vec<_>) = match selection {
+        EpubSelection::Section(name) => {
+            let section = context
+                .sections
+                .iter()
+                .find(|section| &section.name == name)
+                .ok_or_else(|| Error::UnknownSectionError { name: name.clone() })?;
+            let posts = posts
+                .iter()
+                .filter(|(_, post)| post.metadata.directory == section.site_path)
+                .collect();
+            (name, posts)
+        }
+        EpubSelection::Series(name) => {
+            let posts = posts
+                .iter()
+                .filter(|(_, post)| post.metadata.series.as_ref() == Some(name))
+                .collect();
+            (name, posts)
+        }
+    };
+    if posts.is_empty() {
+        return Err(Error::EmptyBookError { name: name.clone() });
+    }
+
+    // series follow `series_order` first, like their navigation links
+    let series_order = |post: &Post| post.metadata.series_order.unwrap_or(u32::MAX);
+    match selection {
+        EpubSelection::Section(_) => posts.sort_by(|(a_name, a), (b_name, b)| {
+            (&a.metadata.published_date, series_order(a), a_name).cmp(&(
+                &b.metadata.published_date,
+                series_order(b),
+                b_name,
+            ))
+        }),
+        EpubSelection::Series(_) => posts.sort_by(|(a_name, a), (b_name, b)| {
+            (series_order(a), &a.metadata.published_date, a_name).cmp(&(
+                series_order(b),
+                &b.metadata.published_date,
+                b_name,
+            ))
+        }),
+    }
+
+    Ok(Book {
+        name: name.clone(),
+        title: format!("{} — {}", context.site_title, name),
+        posts,
+    })
+}
+
+/// Writes `book` as an EPUB 3 package to `path`.
+///
+/// `html` holds each post's content rendered against `CHAPTER_BASE_URL`.
+/// Links between bundled posts are pointed at their chapters, any other
+/// site link at the site origin, and referenced images are copied in from
+/// the built site at `site_dir`.
+pub(crate) fn export_epub(
+    path: &String,
+    site_dir: &String,
+    context: &SiteContext,
+    book: &Book,
+    html: &HashMap<String, String>,
+) -> Result<(), Error> {
+    let chapter_files: Vec<(String, String)> = book
+        .posts
+        .iter()
+        .enumerate()
+        .map(|(index, (_, post))| {
+            let file = format!("{:03}-{}.xhtml", index + 1, post.slug.trim());
+            (post_site_path(post), file)
+        })
+        .collect();
+    let origin = context
+        .site_url
+        .as_deref()
+        .map(|url| url.trim_end_matches('/'));
+
+    let mut chapters = Vec::new();
+    let mut images = BTreeSet::new();
+    for ((name, post), (_, file)) in book.posts.iter().zip(&chapter_files) {
+        let content = html.get(*name).unwrap_or(&post.html);
+        let content = rewrite_links(content, &chapter_files, origin);
+        let (content, headings) = to_xhtml(&content);
+        images.extend(collect_images(&content));
+
+        let title = escape(post.title.trim());
+        let xhtml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <!DOCTYPE html>\n\
+            <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+            lang=\"{lang}\" xml:lang=\"{lang}\">\n\
+            <head>\n<meta charset=\"utf-8\" />\n<title>{title}</title>\n</head>\n\
+            <body>\n<section epub:type=\"chapter\">\n<h1>{title}</h1>\n{content}\n</section>\n</body>\n\
+            </html>\n",
+            lang = escape(&context.language_code),
+            title = title,
+            content = content,
+        );
+        chapters.push(Chapter {
+            file: file.clone(),
+            title,
+            xhtml,
+            headings,
+        });
+    }
+
+    if let Some(directory) = Path::new(path).parent() {
+        ensure_directory(directory).map_err(|e| Error::DirectoryError {
+            source: e,
+            path: path.clone(),
+        })?;
+    }
+    let file = File::create(path).map_err(|e| Error::WriteError {
+        source: e,
+        path: path.clone(),
+    })?;
+    let archive_error = |e| Error::ArchiveError {
+        source: e,
+        path: path.clone(),
+    };
+    let write_error = |e| Error::WriteError {
+        source: e,
+        path: path.clone(),
+    };
+
+    println!("exporting epub -> {}", path);
+    let mut zip = ZipWriter::new(file);
+    let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    // the mimetype must come first, uncompressed
+    let mut entries: Vec<(String, Vec<u8>, FileOptions)> = vec![
+        (
+            String::from("mimetype"),
+            b"application/epub+zip".to_vec(),
+            stored,
+        ),
+        (
+            String::from("META-INF/container.xml"),
+            container_document().into_bytes(),
+            deflated,
+        ),
+        (
+            String::from("OEBPS/content.opf"),
+            package_document(context, book, &chapters, &images).into_bytes(),
+            deflated,
+        ),
+        (
+            String::from("OEBPS/nav.xhtml"),
+            nav_document(context, book, &chapters).into_bytes(),
+            deflated,
+        ),
+    ];
+    for chapter in &chapters {
+        let name = format!("OEBPS/text/{}", chapter.file);
+        entries.push((name, chapter.xhtml.clone().into_bytes(), deflated));
+    }
+    for image in &images {
+        let source = format!("{}/images/{}", site_dir, image);
+        let bytes = std::fs::read(&source).map_err(|e| Error::ImageReadError {
+            source: e,
+            path: source.clone(),
+        })?;
+        entries.push((format!("OEBPS/images/{}", image), bytes, stored));
+    }
+
+    for (name, bytes, options) in entries {
+        zip.start_file(name, options).map_err(archive_error)?;
+        zip.write_all(&bytes).map_err(write_error)?;
+    }
+    zip.finish().map_err(archive_error)?;
+
+    Ok(())
+}
+
+//
+// Package documents
+//
+
+fn container_document() -> String {
+    String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+        <rootfiles>\n\
+        <rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\" />\n\
+        </rootfiles>\n\
+        </container>\n",
+    )
+}
+
+fn package_document(
+    context: &SiteContext,
+    book: &Book,
+    chapters: &[Chapter],
+    images: &BTreeSet<String>,
+) -> String {
+    let identifier = match &context.site_url {
+        Some(url) => format!("{}/{}.epub", url.trim_end_matches('/'), book.file_stem()),
+        None => format!("urn:site-builder:{}", book.file_stem()),
+    };
+    // EPUB wants this exact form, in UTC; without a readable date, the build
+    // time stands in
+    let modified = book
+        .posts
+        .iter()
+        .filter_map(|(_, post)| unix_seconds(&post.metadata.updated_date))
+        .max()
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs() as i64)
+                .unwrap_or_default()
+        });
+    let modified = utc_timestamp(modified);
+    let authors: BTreeSet<&str> = book
+        .posts
+        .iter()
+        .map(|(_, post)| post.metadata.author_name.trim())
+        .collect();
+
+    let mut metadata = format!(
+        "<dc:identifier id=\"book-id\">{}</dc:identifier>\n\
+        <dc:title>{}</dc:title>\n\
+        <dc:language>{}</dc:language>\n\
+        <meta property=\"dcterms:modified\">{}</meta>\n",
+        escape(&identifier),
+        escape(&book.title),
+        escape(&context.language_code),
+        escape(&modified),
+    );
+    for author in authors {
+        metadata.push_str(&format!("<dc:creator>{}</dc:creator>\n", escape(author)));
+    }
+
+    let mut manifest = String::from(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\" />\n",
+    );
+    let mut spine = String::new();
+    for (index, chapter) in chapters.iter().enumerate() {
+        manifest.push_str(&format!(
+            "<item id=\"chapter-{index}\" href=\"text/{file}\" media-type=\"application/xhtml+xml\" />\n",
+            index = index + 1,
+            file = escape(&chapter.file),
+        ));
+        spine.push_str(&format!("<itemref idref=\"chapter-{}\" />\n", index + 1));
+    }
+    for (index, image) in images.iter().enumerate() {
+        manifest.push_str(&format!(
+            "<item id=\"image-{index}\" href=\"images/{image}\" media-type=\"{media_type}\" />\n",
+            index = index + 1,
+            image = escape(image),
+            media_type = image_media_type(image),
+        ));
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"{lang}\">\n\
+        <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{metadata}</metadata>\n\
+        <manifest>\n{manifest}</manifest>\n\
+        <spine>\n{spine}</spine>\n\
+        </package>\n",
+        lang = escape(&context.language_code),
+        metadata = metadata,
+        manifest = manifest,
+        spine = spine,
+    )
+}
+
+fn nav_document(context: &SiteContext, book: &Book, chapters: &[Chapter]) -> String {
+    let mut toc = String::from("<ol>\n");
+    for chapter in chapters {
+        let href = format!("text/{}", chapter.file);
+        toc.push_str(&format!(
+            "<li><a href=\"{}\">{}</a>",
+            escape(&href),
+            chapter.title
+        ));
+        toc.push_str(&nav_list(&href, &chapter.headings));
+        toc.push_str("</li>\n");
+    }
+    toc.push_str("</ol>\n");
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <!DOCTYPE html>\n\
+        <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+        lang=\"{lang}\" xml:lang=\"{lang}\">\n\
+        <head>\n<meta charset=\"utf-8\" />\n<title>{title}</title>\n</head>\n\
+        <body>\n<nav epub:type=\"toc\" id=\"toc\">\n<h1>{title}</h1>\n{toc}</nav>\n</body>\n\
+        </html>\n",
+        lang = escape(&context.language_code),
+        title = escape(&book.title),
+        toc = toc,
+    )
+}
+
+/// Nests headings into `<ol>` lists by level; a heading that skips levels is
+/// nested just once, under the closest heading above it.
+fn nav_list(href: &str, headings: &[Heading]) -> String {
+    let (first, rest) = match headings.split_first() {
+        Some(split) => split,
+        None => return String::new(),
+    };
+    let mut list = String::from("<ol>");
+    let mut levels = vec![first.level];
+    list.push_str(&nav_item(href, first));
+    for heading in rest {
+        if heading.level > *levels.last().unwrap() {
+            levels.push(heading.level);
+            list.push_str("<ol>");
+        } else {
+            list.push_str("</li>");
+            while levels.len() > 1 && heading.level < *levels.last().unwrap() {
+                levels.pop();
+                list.push_str("</ol></li>");
+            }
+        }
+        list.push_str(&nav_item(href, heading));
+    }
+    list.push_str("</li>");
+    list.push_str(&"</ol></li>".repeat(levels.len() - 1));
+    list.push_str("</ol>");
+    list
+}
+
+fn nav_item(href: &str, heading: &Heading) -> String {
+    format!(
+        "<li><a href=\"{}#{}\">{}</a>",
+        escape(href),
+        heading.id,
+        heading.text.trim()
+    )
+}
+
+//
+// Content conversion
+//
+
+/// Points links to bundled posts at their chapters, and any other site link at
+/// the site origin (when there is one).
+fn rewrite_links(html: &str, chapter_files: &[(String, String)], origin: Option<&str>) -> String {
+    let mut html = String::from(html);
+    for (site_path, file) in chapter_files {
+        for quote in ['"', '#'] {
+            let from = format!("href=\"{}/{}{}", CHAPTER_BASE_URL, site_path, quote);
+            let to = format!("href=\"{}{}", file, quote);
+            html = html.replace(&from, &to);
+        }
+    }
+    if let Some(origin) = origin {
+        let from = format!("href=\"{}/", CHAPTER_BASE_URL);
+        html = html.replace(&from, &format!("href=\"{}/", origin));
+    }
+    html
+}
+
+/// Closes void elements so html reads as XHTML, and gives every heading an id
+/// so the nav document can link to it.
+fn to_xhtml(html: &str) -> (String, Vec<Heading>) {
+    let mut xhtml = String::new();
+    let mut headings: Vec<Heading> = Vec::new();
+    let mut open_heading: Option<Heading> = None;
+
+    let html = xml_entities(html);
+    let mut rest = html.as_str();
+    while let Some(start) = rest.find('<') {
+        let text = &rest[..start];
+        xhtml.push_str(text);
+        if let Some(heading) = &mut open_heading {
+            heading.text.push_str(text);
+        }
+        rest = &rest[start..];
+
+        // comments are copied through untouched
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map_or(rest.len(), |end| end + 3);
+            xhtml.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = match rest.find('>') {
+            Some(end) => end + 1,
+            None => break,
+        };
+        let tag = &rest[..end];
+        rest = &rest[end..];
+
+        let closing = tag.starts_with("</");
+        let name = tag
+            .trim_start_matches('<')
+            .trim_start_matches('/')
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .next()
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let level = match name.as_bytes() {
+            [b'h', level @ b'1'..=b'6'] => Some((level - b'0') as usize),
+            _ => None,
+        };
+
+        match level {
+            Some(level) if !closing => {
+                let id = attribute(tag, "id")
+                    .map(String::from)
+                    .unwrap_or_else(|| format!("section-{}", headings.len() + 1));
+                match attribute(tag, "id") {
+                    Some(_) => xhtml.push_str(tag),
+                    None => {
+                        xhtml.push_str(&tag[..tag.len() - 1]);
+                        xhtml.push_str(&format!(" id=\"{}\">", id));
+                    }
+                }
+                open_heading = Some(Heading {
+                    level,
+                    id,
+                    text: String::new(),
+                });
+            }
+            Some(_) => {
+                xhtml.push_str(tag);
+                if let Some(heading) = open_heading.take() {
+                    headings.push(heading);
+                }
+            }
+            None if VOID_ELEMENTS.contains(&name.as_str()) && !tag.ends_with("/>") => {
+                xhtml.push_str(tag[..tag.len() - 1].trim_end());
+                xhtml.push_str(" />");
+            }
+            None => xhtml.push_str(tag),
+        }
+    }
+    xhtml.push_str(rest);
+
+    (xhtml, headings)
+}
+
+/// Replaces html's named character references, which XML doesn't know
+/// beyond its own five, with numeric ones. An `&` that doesn't start a
+/// reference this knows is escaped.
+fn xml_entities(html: &str) -> String {
+    let mut xml = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('&') {
+        xml.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let name = rest
+            .find(';')
+            .map(|end| &rest[..end])
+            .filter(|name| name.len() <= 32 && !name.is_empty());
+        let code_point = name.and_then(|name| match name {
+            "amp" | "lt" | "gt" | "quot" | "apos" => None,
+            _ => LATIN_1_ENTITIES
+                .iter()
+                .position(|entity| *entity == name)
+                .map(|index| index as u32 + 160)
+                .or_else(|| {
+                    NAMED_ENTITIES
+                        .iter()
+                        .find(|(entity, _)| *entity == name)
+                        .map(|(_, code_point)| *code_point)
+                }),
+        });
+        match (name, code_point) {
+            (Some(name), Some(code_point)) => {
+                xml.push_str(&format!("&#{};", code_point));
+                rest = &rest[name.len() + 1..];
+            }
+            (Some(name), None) if is_xml_reference(name) => xml.push('&'),
+            _ => xml.push_str("&amp;"),
+        }
+    }
+    xml.push_str(rest);
+    xml
+}
+
+fn is_xml_reference(name: &str) -> bool {
+    match name.strip_prefix('#') {
+        Some(number) => match number.strip_prefix(['x', 'X']) {
+            Some(hex) => !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()),
+            None => !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()),
+        },
+        None => ["amp", "lt", "gt", "quot", "apos"].contains(&name),
+    }
+}
+
+/// The named references for U+00A0 through U+00FF, in order.
+static LATIN_1_ENTITIES: [&str; 96] = [
+    "nbsp", "iexcl", "cent", "pound", "curren", "yen", "brvbar", "sect", "uml", "copy", "ordf",
+    "laquo", "not", "shy", "reg", "macr", "deg", "plusmn", "sup2", "sup3", "acute", "micro",
+    "para", "middot", "cedil", "sup1", "ordm", "raquo", "frac14", "frac12", "frac34", "iquest",
+    "Agrave", "Aacute", "Acirc", "Atilde", "Auml", "Aring", "AElig", "Ccedil", "Egrave", "Eacute",
+    "Ecirc", "Euml", "Igrave", "Iacute", "Icirc", "Iuml", "ETH", "Ntilde", "Ograve", "Oacute",
+    "Ocirc", "Otilde", "Ouml", "times", "Oslash", "Ugrave", "Uacute", "Ucirc", "Uuml", "Yacute",
+    "THORN", "szlig", "agrave", "aacute", "acirc", "atilde", "auml", "aring", "aelig", "ccedil",
+    "egrave", "eacute", "ecirc", "euml", "igrave", "iacute", "icirc", "iuml", "eth", "ntilde",
+    "ograve", "oacute", "ocirc", "otilde", "ouml", "divide", "oslash", "ugrave", "uacute", "ucirc",
+    "uuml", "yacute", "thorn", "yuml",
+];
+
+/// Other named references that turn up in hand-written html.
+static NAMED_ENTITIES: [(&str, u32); 45] = [
+    ("OElig", 338),
+    ("oelig", 339),
+    ("Scaron", 352),
+    ("scaron", 353),
+    ("Yuml", 376),
+    ("fnof", 402),
+    ("circ", 710),
+    ("tilde", 732),
+    ("ensp", 8194),
+    ("emsp", 8195),
+    ("thinsp", 8201),
+    ("zwnj", 8204),
+    ("zwj", 8205),
+    ("lrm", 8206),
+    ("rlm", 8207),
+    ("ndash", 8211),
+    ("mdash", 8212),
+    ("lsquo", 8216),
+    ("rsquo", 8217),
+    ("sbquo", 8218),
+    ("ldquo", 8220),
+    ("rdquo", 8221),
+    ("bdquo", 8222),
+    ("dagger", 8224),
+    ("Dagger", 8225),
+    ("bull", 8226),
+    ("hellip", 8230),
+    ("permil", 8240),
+    ("prime", 8242),
+    ("Prime", 8243),
+    ("lsaquo", 8249),
+    ("rsaquo", 8250),
+    ("euro", 8364),
+    ("trade", 8482),
+    ("larr", 8592),
+    ("uarr", 8593),
+    ("rarr", 8594),
+    ("darr", 8595),
+    ("harr", 8596),
+    ("minus", 8722),
+    ("infin", 8734),
+    ("ne", 8800),
+    ("le", 8804),
+    ("ge", 8805),
+    ("hearts", 9829),
+];
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!(" {}=\"", name))? + name.len() + 3;
+    let end = tag[start..].find('"')?;
+    Some(&tag[start..start + end])
+}
+
+/// Every image under `images/` referenced by the xhtml, including srcsets.
+fn collect_images(xhtml: &str) -> Vec<String> {
+    xhtml
+        .match_indices(IMAGES_PREFIX)
+        .map(|(start, _)| {
+            let url = &xhtml[start + IMAGES_PREFIX.len()..];
+            let end = url
+                .find(|c: char| c == '"' || c == ',' || c.is_whitespace())
+                .unwrap_or(url.len());
+            String::from(&url[..end])
+        })
+        .collect()
+}
+
+fn image_media_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("webp") => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::new();
+    // writing to a String can't fail
+    escape_html(&mut escaped, s).unwrap();
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        collect_book, collect_images, nav_list, package_document, to_xhtml, xml_entities,
+        EpubSelection,
+    };
+    use crate::parsing::{Post, SiteContext};
+    use serde_json::json;
+    use std::collections::{BTreeSet, HashMap};
+
+    fn context() -> SiteContext {
+        serde_json::from_value(json!({
+            "site_title": "Site",
+            "language_code": "en",
+            "sections": [],
+        }))
+        .unwrap()
+    }
+
+    fn post(date: &str, series_order: u32) -> Post {
+        Post::try_from(json!({
+            "metadata": {
+                "author_name": "author",
+                "published_date": date,
+                "series": "trip",
+                "series_order": series_order,
+            },
+            "title": "title",
+            "slug": date,
+            "content": "",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_xhtml_closes_void_elements_and_ids_headings() {
+        let (xhtml, headings) =
+            to_xhtml("<h2>One &amp; <em>two</em></h2><p>a<br>b</p><img src=\"x\"><hr />");
+        assert_eq!(
+            xhtml,
+            "<h2 id=\"section-1\">One &amp; <em>two</em></h2><p>a<br />b</p><img src=\"x\" /><hr />"
+        );
+        assert_eq!(headings.len(), 1);
+        assert_eq!(headings[0].text, "One &amp; two");
+    }
+
+    #[test]
+    fn test_named_entities_become_numeric() {
+        assert_eq!(
+            xml_entities("a&nbsp;b &amp; &#160; &mdash; &bogus; R&D"),
+            "a&#160;b &amp; &#160; &#8212; &amp;bogus; R&amp;D"
+        );
+    }
+
+    #[test]
+    fn test_nav_nests_headings_by_level() {
+        let (_, headings) = to_xhtml("<h2>a</h2><h3>b</h3><h2 id=\"c\">c</h2>");
+        assert_eq!(
+            nav_list("t.xhtml", &headings),
+            "<ol><li><a href=\"t.xhtml#section-1\">a</a><ol><li><a href=\"t.xhtml#section-2\">b</a>\
+            </li></ol></li><li><a href=\"t.xhtml#c\">c</a></li></ol>"
+        );
+    }
+
+    #[test]
+    fn test_collects_srcset_images() {
+        let images = collect_images(
+            "<img src=\"../images/a-1-16.jpg\" srcset=\"../images/a-1-8.jpg 8w, ../images/a-1-16.jpg 16w\" />",
+        );
+        assert_eq!(images, ["a-1-16.jpg", "a-1-8.jpg", "a-1-16.jpg"]);
+    }
+
+    #[test]
+    fn test_series_books_follow_series_order() {
+        let context = context();
+        let posts = HashMap::from([
+            (String::from("a"), post("2022-03-01", 1)),
+            (String::from("b"), post("2022-01-01", 2)),
+            (String::from("c"), post("2022-02-01", 3)),
+        ]);
+        let book = collect_book(
+            &context,
+            &posts,
+            &EpubSelection::Series(String::from("trip")),
+        )
+        .unwrap();
+        let names: Vec<&str> = book.posts.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_modified_date_falls_back_to_build_time() {
+        let context = context();
+        let posts = HashMap::from([(String::from("a"), post("someday", 1))]);
+        let book = collect_book(
+            &context,
+            &posts,
+            &EpubSelection::Series(String::from("trip")),
+        )
+        .unwrap();
+        let opf = package_document(&context, &book, &[], &BTreeSet::new());
+        let modified = opf
+            .split("<meta property=\"dcterms:modified\">")
+            .nth(1)
+            .and_then(|rest| rest.split('<').next())
+            .unwrap();
+        assert_eq!(modified.len(), "2022-01-01T00:00:00Z".len());
+        assert!(modified.ends_with('Z'));
+    }
+}
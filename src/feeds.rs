@@ -0,0 +1,327 @@
+use crate::files::{ensure_directory, write_file_contents, Error as FilesError};
+use crate::navigation::post_site_path;
+use crate::parsing::{Attachment, Post, SiteContext};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Couldn't serialize feed '{path}'")]
+    SerializeError {
+        source: serde_json::Error,
+        path: String,
+    },
+
+    #[error("Couldn't write feed '{path}'")]
+    WriteError { source: FilesError, path: String },
+}
+
+static JSON_FEED_VERSION: &str = "https://jsonfeed.org/version/1.1";
+static DEFAULT_FEED_FILENAME: &str = "feed.json";
+static DEFAULT_ITEM_COUNT: usize = 20;
+
+/// The `feed` section of config.json, either site-wide or on a section.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub(crate) struct FeedConfig {
+    pub path: Option<String>,
+    pub title: Option<String>,
+    pub item_count: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    home_page_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    feed_url: Option<String>,
+    language: &'a str,
+    items: Vec<JsonFeedItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonFeedItem<'a> {
+    id: String,
+    url: String,
+    title: &'a str,
+    content_html: &'a str,
+    date_published: String,
+    date_modified: String,
+    authors: Vec<JsonFeedAuthor<'a>>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    tags: &'a [String],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    attachments: &'a [Attachment],
+}
+
+#[derive(Serialize)]
+struct JsonFeedAuthor<'a> {
+    name: &'a str,
+}
+
+/// A feed to write: where it lives, and the posts it's built from.
+pub(crate) struct FeedDescriptor<'a> {
+    pub config: &'a FeedConfig,
+    pub title: String,
+    pub home_path: String,
+    pub default_path: String,
+    pub posts: Vec<(&'a String, &'a Post)>,
+}
+
+impl<'a> FeedDescriptor<'a> {
+    /// The site-scoped path this feed is written to.
+    pub(crate) fn site_path(&self) -> String {
+        self.config
+            .path
+            .clone()
+            .unwrap_or_else(|| self.default_path.clone())
+    }
+}
+
+/// Every feed configured for the site: one site-wide feed, and one for each
+/// section that declares a `feed`, made of the posts in its directory.
+pub(crate) fn collect_feeds<'a>(
+    context: &'a SiteContext,
+    posts: &'a HashMap<String, Post>,
+) -> Vec<FeedDescriptor<'a>> {
+    let mut feeds = Vec::new();
+    if let Some(config) = &context.feed {
+        feeds.push(FeedDescriptor {
+            config,
+            title: context.site_title.clone(),
+            home_path: String::new(),
+            default_path: String::from(DEFAULT_FEED_FILENAME),
+            posts: posts.iter().collect(),
+        });
+    }
+    for section in &context.sections {
+        if let Some(config) = &section.feed {
+            let default_path = match section.site_path.as_str() {
+                "" => String::from(DEFAULT_FEED_FILENAME),
+                site_path => format!("{}/{}", site_path, DEFAULT_FEED_FILENAME),
+            };
+            feeds.push(FeedDescriptor {
+                config,
+                title: format!("{} — {}", context.site_title, section.name),
+                home_path: section.site_path.clone(),
+                default_path,
+                posts: posts
+                    .iter()
+                    .filter(|(_, post)| post.metadata.directory == section.site_path)
+                    .collect(),
+            });
+        }
+    }
+    feeds
+}
+
+/// Writes a JSON Feed 1.1 document of the newest posts in `feed`.
+///
+/// `html` holds each post's content with urls resolved against the site
+/// origin. Without a `site_url`, urls are left root-absolute.
+pub(crate) fn export_json_feed(
+    output_dir: &String,
+    context: &SiteContext,
+    feed: &FeedDescriptor,
+    html: &HashMap<String, String>,
+) -> Result<(), Error> {
+    let origin = context
+        .site_url
+        .as_ref()
+        .map(|url| url.trim_end_matches('/'))
+        .unwrap_or("");
+    let absolute = |site_path: &str| format!("{}/{}", origin, site_path);
+
+    let mut posts = feed.posts.clone();
+    posts.sort_by(|(a_name, a), (b_name, b)| {
+        (&b.metadata.published_date, a_name).cmp(&(&a.metadata.published_date, b_name))
+    });
+    posts.truncate(feed.config.item_count.unwrap_or(DEFAULT_ITEM_COUNT));
+
+    let items = posts
+        .into_iter()
+        .map(|(name, post)| {
+            let url = absolute(&post_site_path(post));
+            JsonFeedItem {
+                id: url.clone(),
+                url,
+                title: &post.title,
+                content_html: html.get(name).unwrap_or(&post.html),
+                date_published: rfc3339_date(&post.metadata.published_date),
+                date_modified: rfc3339_date(&post.metadata.updated_date),
+                authors: vec![JsonFeedAuthor {
+                    name: &post.metadata.author_name,
+                }],
+                tags: &post.metadata.tags,
+                attachments: &post.metadata.attachments,
+            }
+        })
+        .collect();
+
+    let site_path = feed.site_path();
+    let has_origin = context.site_url.is_some();
+    let json_feed = JsonFeed {
+        version: JSON_FEED_VERSION,
+        title: feed
+            .config
+            .title
+            .clone()
+            .unwrap_or_else(|| feed.title.clone()),
+        home_page_url: has_origin.then(|| absolute(&feed.home_path)),
+        feed_url: has_origin.then(|| absolute(&site_path)),
+        language: &context.language_code,
+        items,
+    };
+
+    let path = format!("{}/{}", output_dir, site_path);
+    let json = serde_json::to_string_pretty(&json_feed).map_err(|e| Error::SerializeError {
+        source: e,
+        path: path.clone(),
+    })?;
+    if let Some(directory) = Path::new(&path).parent() {
+        ensure_directory(directory).map_err(|e| Error::WriteError {
+            source: e,
+            path: path.clone(),
+        })?;
+    }
+    println!("exporting feed -> {}", path);
+    write_file_contents(&json, &path).map_err(|e| Error::WriteError {
+        source: e,
+        path: path.clone(),
+    })
+}
+
+/// Dates in metadata are usually bare `YYYY-MM-DD`, which JSON Feed doesn't
+/// accept; every date is normalized to an RFC 3339 UTC timestamp, with bare
+/// dates pinned to midnight. Dates that can't be read are passed through.
+pub(crate) fn rfc3339_date(date: &str) -> String {
+    match unix_seconds(date) {
+        Some(seconds) => utc_timestamp(seconds),
+        None => String::from(date.trim()),
+    }
+}
+
+/// Seconds since the epoch for a bare `YYYY-MM-DD` date (at midnight UTC) or
+/// an RFC 3339 timestamp, so dates written with different offsets compare.
+pub(crate) fn unix_seconds(date: &str) -> Option<i64> {
+    let date = date.trim();
+    let (day, time) = date.split_once(['T', 't', ' ']).unwrap_or((date, ""));
+    let mut day = day.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (day.next()?.ok()?, day.next()?.ok()?, day.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let (clock, offset) = time.split_at(time.find(['Z', 'z', '+', '-']).unwrap_or(time.len()));
+    let mut clock = clock.split(':').filter(|part| !part.is_empty());
+    let hours: i64 = clock.next().map_or(Some(0), |hours| hours.parse().ok())?;
+    let minutes: i64 = clock
+        .next()
+        .map_or(Some(0), |minutes| minutes.parse().ok())?;
+    // fractions of a second are dropped
+    let seconds: i64 = clock
+        .next()
+        .map_or(Some(0), |seconds| seconds.split('.').next()?.parse().ok())?;
+    let offset = match offset {
+        "" | "Z" | "z" => 0,
+        offset => {
+            let digits = offset[1..].replace(':', "");
+            let hours: i64 = digits.get(..2)?.parse().ok()?;
+            let minutes: i64 = match digits.get(2..)? {
+                "" => 0,
+                minutes => minutes.parse().ok()?,
+            };
+            match offset.starts_with('-') {
+                true => -(hours * 3600 + minutes * 60),
+                false => hours * 3600 + minutes * 60,
+            }
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    Some(days * 86400 + hours * 3600 + minutes * 60 + seconds - offset)
+}
+
+/// Formats seconds since the epoch as `YYYY-MM-DDThh:mm:ssZ`.
+pub(crate) fn utc_timestamp(seconds: i64) -> String {
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// Converts a date in the proleptic Gregorian calendar to days since
+/// 1970-01-01.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) in the
+/// proleptic Gregorian calendar.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{civil_from_days, rfc3339_date, unix_seconds, utc_timestamp};
+
+    #[test]
+    fn test_dates_normalize_to_utc() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19000), (2022, 1, 8));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+
+        let utc = |date| unix_seconds(date).map(utc_timestamp);
+        assert_eq!(utc("2022-01-08").as_deref(), Some("2022-01-08T00:00:00Z"));
+        assert_eq!(
+            utc("2022-01-08T01:30:00.5+02:00").as_deref(),
+            Some("2022-01-07T23:30:00Z")
+        );
+        assert_eq!(
+            utc("2000-02-28T20:00-0500").as_deref(),
+            Some("2000-02-29T01:00:00Z")
+        );
+        assert_eq!(utc("someday"), None);
+    }
+
+    #[test]
+    fn test_feed_dates_are_rfc3339() {
+        assert_eq!(rfc3339_date("2022-01-08"), "2022-01-08T00:00:00Z");
+        assert_eq!(rfc3339_date("2022-01-08T01:30"), "2022-01-08T01:30:00Z");
+        assert_eq!(
+            rfc3339_date("2022-01-08T01:30:00+02:00"),
+            "2022-01-07T23:30:00Z"
+        );
+        assert_eq!(rfc3339_date("Tuesday"), "Tuesday");
+    }
+}
@@ -173,7 +173,7 @@ pub(crate) fn load_component_files(
     Ok(components)
 }
 
-fn path_to_string(path: impl AsRef<Path>) -> String {
+pub(crate) fn path_to_string(path: impl AsRef<Path>) -> String {
     path.as_ref().to_string_lossy().to_string()
 }
 
@@ -0,0 +1,64 @@
+use crate::files::{
+    get_paths_from_glob, path_to_string, read_file_contents, write_file_contents,
+    Error as FilesError,
+};
+use crate::parsing::{format_blocks, Error as ParsingError};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Couldn't find content files")]
+    SearchError { source: FilesError },
+
+    #[error("Couldn't read '{path}'")]
+    ReadError { source: FilesError, path: String },
+
+    #[error("Couldn't format '{path}'")]
+    ParseError { source: ParsingError, path: String },
+
+    #[error("Couldn't write '{path}'")]
+    WriteError { source: FilesError, path: String },
+
+    #[error("Block files aren't formatted:\n{paths}")]
+    UnformattedError { paths: String },
+}
+
+/// Formats every block file matching `content_glob` in place. With `check`,
+/// nothing is written, and any file that would change is an error.
+///
+/// Files that aren't block files (like included markdown) are left alone.
+pub(crate) fn format_content(content_glob: &String, check: bool) -> Result<(), Error> {
+    let paths = get_paths_from_glob(content_glob).map_err(|e| Error::SearchError { source: e })?;
+
+    let mut unformatted = Vec::new();
+    for path in paths {
+        let name = path_to_string(&path);
+        let contents = read_file_contents(&path).map_err(|e| Error::ReadError {
+            source: e,
+            path: name.clone(),
+        })?;
+        let formatted = match format_blocks(&contents).map_err(|e| Error::ParseError {
+            source: e,
+            path: name.clone(),
+        })? {
+            Some(formatted) if formatted != contents => formatted,
+            _ => continue,
+        };
+
+        if !check {
+            write_file_contents(&formatted, &path).map_err(|e| Error::WriteError {
+                source: e,
+                path: name.clone(),
+            })?;
+            println!("formatted {}", name);
+        }
+        unformatted.push(name);
+    }
+
+    match check && !unformatted.is_empty() {
+        true => Err(Error::UnformattedError {
+            paths: unformatted.join("\n"),
+        }),
+        false => Ok(()),
+    }
+}
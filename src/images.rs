@@ -0,0 +1,614 @@
+use crate::files::{
+    ensure_directory, get_extension, get_paths_from_glob, get_stripped_base_path_string,
+    path_to_string, Error as FilesError,
+};
+use crate::parsing::{djot_raw_html, djot_text};
+use base64ct::{Base64Url, Base64UrlUnpadded, Encoding};
+use blake2s_simd::Params;
+use image::codecs::{
+    jpeg::JpegEncoder,
+    webp::{WebPEncoder, WebPQuality},
+};
+use image::{imageops::FilterType, DynamicImage};
+use pulldown_cmark::{escape::escape_html, CowStr, Event, Tag};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf, StripPrefixError};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Couldn't find source images")]
+    ImageSearchError { source: FilesError },
+
+    #[error("Couldn't find '{path}' under the images directory")]
+    ImagePathError {
+        source: StripPrefixError,
+        path: String,
+    },
+
+    #[error("Couldn't read image '{path}'")]
+    ImageReadError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Couldn't decode image '{path}'")]
+    ImageDecodeError {
+        source: image::ImageError,
+        path: String,
+    },
+
+    #[error("Couldn't encode image '{path}'")]
+    ImageEncodeError {
+        source: image::ImageError,
+        path: String,
+    },
+
+    #[error("Couldn't create image directory '{path}'")]
+    ImageDirectoryError { source: FilesError, path: String },
+
+    #[error("Couldn't write image '{path}'")]
+    ImageWriteError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Couldn't remove stale image '{path}'")]
+    StaleImageRemoveError {
+        source: std::io::Error,
+        path: String,
+    },
+}
+
+static PLACEHOLDER_OPEN: &str = "<!--image:";
+static PLACEHOLDER_CLOSE: &str = "-->";
+static DEFAULT_WIDTHS: [u32; 3] = [480, 960, 1440];
+static DEFAULT_SIZES: &str = "100vw";
+static DEFAULT_JPEG_QUALITY: u8 = 80;
+static SOURCE_EXTENSIONS: [&str; 4] = ["jpg", "jpeg", "png", "webp"];
+static VARIANT_EXTENSIONS: [&str; 2] = ["jpg", "webp"];
+
+/// The `images` section of config.json.
+#[derive(Deserialize, Serialize, Clone, Debug, Default)]
+pub(crate) struct ImageConfig {
+    pub widths: Option<Vec<u32>>,
+    pub sizes: Option<String>,
+    pub jpeg_quality: Option<u8>,
+    /// Lossy WebP quality, the same as `jpeg_quality` when it isn't set.
+    pub webp_quality: Option<u8>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct ImageVariant {
+    pub width: u32,
+    pub height: u32,
+    pub webp_url: String,
+    pub jpeg_url: String,
+}
+
+/// A source image, and the resized variants written for it.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct ProcessedImage {
+    pub width: u32,
+    pub height: u32,
+    pub variants: Vec<ImageVariant>,
+}
+
+pub(crate) struct ProcessedImages {
+    pub images: HashMap<String, ProcessedImage>,
+    pub sizes: String,
+}
+
+/// An image reference left in rendered content, written as
+/// `![alt](images/path.jpg)` in markdown or `image(src=...)` in templates.
+#[derive(Deserialize, Serialize, Debug)]
+pub(crate) struct ImageReference {
+    pub src: String,
+    pub alt: String,
+    pub sizes: Option<String>,
+}
+
+/// Resizes every image under `source_dir` to the configured widths and writes
+/// lossy WebP and JPEG variants to `output_dir`.
+///
+/// Re-encoding drops all metadata (including EXIF), once the EXIF orientation
+/// has been applied. Variant filenames carry a hash of the source and the
+/// encoding settings, so images that haven't changed are not processed again,
+/// and variants no image produced anymore are removed.
+pub(crate) fn process_images(
+    source_dir: &String,
+    output_dir: &String,
+    config: &ImageConfig,
+    log: bool,
+) -> Result<ProcessedImages, Error> {
+    let widths = config
+        .widths
+        .clone()
+        .unwrap_or_else(|| DEFAULT_WIDTHS.to_vec());
+    let quality = Quality {
+        jpeg: config.jpeg_quality.unwrap_or(DEFAULT_JPEG_QUALITY),
+        webp: config
+            .webp_quality
+            .or(config.jpeg_quality)
+            .unwrap_or(DEFAULT_JPEG_QUALITY),
+    };
+    let sizes = config
+        .sizes
+        .clone()
+        .unwrap_or_else(|| String::from(DEFAULT_SIZES));
+
+    let mut images = HashMap::new();
+    if !Path::new(source_dir).is_dir() {
+        return Ok(ProcessedImages { images, sizes });
+    }
+
+    // cameras like to name files `IMG_0001.JPG`
+    let pattern = format!("{}/**/*", source_dir);
+    let paths = get_paths_from_glob(&pattern)
+        .map_err(|e| Error::ImageSearchError { source: e })?
+        .into_iter()
+        .filter(|path| has_extension(path, &SOURCE_EXTENSIONS));
+
+    for path in paths {
+        let name = get_stripped_base_path_string(&path, source_dir).map_err(|e| {
+            Error::ImagePathError {
+                source: e,
+                path: path_to_string(&path),
+            }
+        })?;
+        let image = process_image(&path, &name, output_dir, &widths, &quality, log)?;
+        images.insert(name, image);
+    }
+    remove_stale_variants(output_dir, &images, log)?;
+
+    Ok(ProcessedImages { images, sizes })
+}
+
+struct Quality {
+    jpeg: u8,
+    webp: u8,
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    get_extension(path).is_ok_and(|extension| {
+        extensions
+            .iter()
+            .any(|candidate| extension.eq_ignore_ascii_case(candidate))
+    })
+}
+
+/// Removes variants in the output's `images/` that weren't just produced, left
+/// over from source images that were deleted, renamed or changed.
+fn remove_stale_variants(
+    output_dir: &String,
+    images: &HashMap<String, ProcessedImage>,
+    log: bool,
+) -> Result<(), Error> {
+    let current: HashSet<String> = images
+        .values()
+        .flat_map(|image| &image.variants)
+        .flat_map(|variant| [&variant.webp_url, &variant.jpeg_url])
+        .map(|url| format!("{}/{}", output_dir, url))
+        .collect();
+
+    let pattern = format!("{}/images/**/*", output_dir);
+    let paths = get_paths_from_glob(&pattern).map_err(|e| Error::ImageSearchError { source: e })?;
+    for path in paths {
+        let path_string = path_to_string(&path);
+        if !has_extension(&path, &VARIANT_EXTENSIONS) || current.contains(&path_string) {
+            continue;
+        }
+        if log {
+            println!("removing stale image {}", path_string);
+        }
+        std::fs::remove_file(&path).map_err(|e| Error::StaleImageRemoveError {
+            source: e,
+            path: path_string,
+        })?;
+    }
+
+    Ok(())
+}
+
+fn process_image(
+    path: &PathBuf,
+    name: &str,
+    output_dir: &String,
+    widths: &[u32],
+    quality: &Quality,
+    log: bool,
+) -> Result<ProcessedImage, Error> {
+    let path_string = path.to_string_lossy().to_string();
+    let bytes = std::fs::read(path).map_err(|e| Error::ImageReadError {
+        source: e,
+        path: path_string.clone(),
+    })?;
+    let hash = Params::new()
+        .hash_length(8)
+        .to_state()
+        .update(&bytes)
+        .update(&[quality.jpeg, quality.webp])
+        .finalize();
+    let hash = Base64UrlUnpadded::encode_string(hash.as_bytes());
+
+    let orientation = exif_orientation(&bytes).unwrap_or(1);
+    let (width, height) = image::image_dimensions(path).map_err(|e| Error::ImageDecodeError {
+        source: e,
+        path: path_string.clone(),
+    })?;
+    // orientations 5 through 8 turn the image on its side
+    let (width, height) = match orientation {
+        5..=8 => (height, width),
+        _ => (width, height),
+    };
+
+    // never upscale; an image narrower than every width keeps its own
+    let mut variant_widths: Vec<u32> = widths.iter().copied().filter(|w| *w < width).collect();
+    variant_widths.push(width.min(*widths.iter().max().unwrap_or(&width)));
+    variant_widths.sort_unstable();
+    variant_widths.dedup();
+
+    let (directory, stem) = match name.rsplit_once('/') {
+        Some((directory, file)) => (format!("/{}", directory), file),
+        None => (String::new(), name),
+    };
+    let stem = stem.rsplit_once('.').map_or(stem, |(stem, _)| stem);
+    let out_directory = format!("{}/images{}", output_dir, directory);
+    ensure_directory(&out_directory).map_err(|e| Error::ImageDirectoryError {
+        source: e,
+        path: out_directory.clone(),
+    })?;
+
+    let mut source = None;
+    let mut variants = Vec::new();
+    for variant_width in variant_widths {
+        let variant_height =
+            ((height as u64 * variant_width as u64 + width as u64 / 2) / width as u64) as u32;
+        let filename = format!("{}-{}-{}", stem, hash, variant_width);
+        let webp_path = format!("{}/{}.webp", out_directory, filename);
+        let jpeg_path = format!("{}/{}.jpg", out_directory, filename);
+
+        if !Path::new(&webp_path).exists() || !Path::new(&jpeg_path).exists() {
+            if log {
+                println!("processing image {} @ {}w", name, variant_width);
+            }
+            if source.is_none() {
+                let decoded =
+                    image::load_from_memory(&bytes).map_err(|e| Error::ImageDecodeError {
+                        source: e,
+                        path: path_string.clone(),
+                    })?;
+                source = Some(apply_orientation(decoded, orientation));
+            }
+            let resized = source.as_ref().unwrap().resize_exact(
+                variant_width,
+                variant_height,
+                FilterType::Lanczos3,
+            );
+
+            let rgba = resized.to_rgba8();
+            // lossless WebP is larger than the JPEG for photos, which would
+            // defeat serving it first
+            #[allow(deprecated)]
+            write_image(&webp_path, |writer| {
+                WebPEncoder::new_with_quality(writer, WebPQuality::lossy(quality.webp)).encode(
+                    &rgba,
+                    variant_width,
+                    variant_height,
+                    image::ColorType::Rgba8,
+                )
+            })?;
+            let rgb = resized.to_rgb8();
+            write_image(&jpeg_path, |writer| {
+                JpegEncoder::new_with_quality(writer, quality.jpeg).encode(
+                    &rgb,
+                    variant_width,
+                    variant_height,
+                    image::ColorType::Rgb8,
+                )
+            })?;
+        }
+
+        let url_base = format!("images{}/{}", directory, filename);
+        variants.push(ImageVariant {
+            width: variant_width,
+            height: variant_height,
+            webp_url: format!("{}.webp", url_base),
+            jpeg_url: format!("{}.jpg", url_base),
+        });
+    }
+
+    Ok(ProcessedImage {
+        width,
+        height,
+        variants,
+    })
+}
+
+fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+/// Reads the orientation tag from a JPEG's EXIF segment.
+fn exif_orientation(bytes: &[u8]) -> Option<u16> {
+    if bytes.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut offset = 2;
+    loop {
+        let marker = bytes.get(offset..offset + 4)?;
+        // the image data starts at SOS, and no metadata follows it
+        if marker[0] != 0xFF || marker[1] == 0xDA {
+            return None;
+        }
+        let length = u16::from_be_bytes([marker[2], marker[3]]) as usize;
+        let segment = bytes.get(offset + 4..offset + 2 + length)?;
+        if marker[1] == 0xE1 {
+            if let Some(tiff) = segment.strip_prefix(b"Exif\0\0") {
+                return tiff_orientation(tiff);
+            }
+        }
+        offset += 2 + length;
+    }
+}
+
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| {
+        let bytes = [*tiff.get(at)?, *tiff.get(at + 1)?];
+        Some(match big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    };
+    let u32_at = |at: usize| {
+        let bytes = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    };
+
+    let ifd = u32_at(4)? as usize;
+    for entry in 0..u16_at(ifd)? as usize {
+        let entry = ifd + 2 + entry * 12;
+        if u16_at(entry)? == 0x0112 {
+            return u16_at(entry + 8);
+        }
+    }
+    None
+}
+
+fn write_image(
+    path: &String,
+    encode: impl FnOnce(&mut BufWriter<File>) -> image::ImageResult<()>,
+) -> Result<(), Error> {
+    let file = File::create(path).map_err(|e| Error::ImageWriteError {
+        source: e,
+        path: path.clone(),
+    })?;
+    let mut writer = BufWriter::new(file);
+    encode(&mut writer).map_err(|e| Error::ImageEncodeError {
+        source: e,
+        path: path.clone(),
+    })
+}
+
+impl ProcessedImage {
+    /// A `<picture>` with WebP and JPEG srcsets, sized by the largest variant.
+    pub(crate) fn to_html(&self, base_url: &str, alt: &str, sizes: &str) -> String {
+        let srcset = |url: fn(&ImageVariant) -> &String| {
+            self.variants
+                .iter()
+                .map(|v| format!("{}/{} {}w", base_url, url(v), v.width))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        // variants are never empty, there's always at least the source width
+        let largest = self.variants.last().unwrap();
+        let mut escaped_alt = String::new();
+        escape_html(&mut escaped_alt, alt).unwrap();
+        format!(
+            "<picture><source type=\"image/webp\" srcset=\"{webp}\" sizes=\"{sizes}\">\
+            <img src=\"{base_url}/{src}\" srcset=\"{jpeg}\" sizes=\"{sizes}\" \
+            width=\"{width}\" height=\"{height}\" alt=\"{alt}\" loading=\"lazy\"></picture>",
+            webp = srcset(|v| &v.webp_url),
+            jpeg = srcset(|v| &v.jpeg_url),
+            src = largest.jpeg_url,
+            width = largest.width,
+            height = largest.height,
+            sizes = sizes,
+            alt = escaped_alt,
+            base_url = base_url,
+        )
+    }
+}
+
+//
+// Placeholders
+//
+// Image references are resolved when content is rendered, once the
+// destination (and so the base url) is known.
+
+/// Turns markdown images that point into `images/` into image placeholders.
+pub(crate) fn expand_markdown_events<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+) -> impl Iterator<Item = Event<'a>> {
+    let mut expanded: Vec<Event<'a>> = Vec::new();
+    let mut image: Option<(String, String)> = None;
+
+    for event in events {
+        match (&mut image, event) {
+            (None, Event::Start(Tag::Image(link_type, destination, title))) => {
+                match destination.trim_start_matches('/').strip_prefix("images/") {
+                    Some(src) => image = Some((String::from(src), String::new())),
+                    None => expanded.push(Event::Start(Tag::Image(link_type, destination, title))),
+                }
+            }
+            (Some((_, alt)), Event::Text(text) | Event::Code(text)) => alt.push_str(&text),
+            (Some(_), Event::End(Tag::Image(..))) => {
+                // unwrapped because we just matched Some
+                let (src, alt) = image.take().unwrap();
+                let reference = ImageReference {
+                    src,
+                    alt,
+                    sizes: None,
+                };
+                expanded.push(Event::Html(CowStr::from(placeholder(&reference))));
+            }
+            (Some(_), _) => {}
+            (None, event) => expanded.push(event),
+        }
+    }
+
+    expanded.into_iter()
+}
+
+/// Turns djot images that point into `images/` into image placeholders.
+pub(crate) fn expand_djot_events<'s>(
+    events: impl Iterator<Item = jotdown::Event<'s>>,
+) -> impl Iterator<Item = jotdown::Event<'s>> {
+    let mut expanded: Vec<jotdown::Event<'s>> = Vec::new();
+    let mut image: Option<(String, String)> = None;
+
+    for event in events {
+        match (&mut image, event) {
+            (
+                None,
+                jotdown::Event::Start(
+                    jotdown::Container::Image(destination, link_type),
+                    attributes,
+                ),
+            ) => match destination.trim_start_matches('/').strip_prefix("images/") {
+                Some(src) => image = Some((String::from(src), String::new())),
+                None => expanded.push(jotdown::Event::Start(
+                    jotdown::Container::Image(destination, link_type),
+                    attributes,
+                )),
+            },
+            (Some(_), jotdown::Event::End(jotdown::Container::Image(..))) => {
+                // unwrapped because we just matched Some
+                let (src, alt) = image.take().unwrap();
+                let reference = ImageReference {
+                    src,
+                    alt,
+                    sizes: None,
+                };
+                expanded.extend(djot_raw_html(placeholder(&reference)));
+            }
+            (Some((_, alt)), event) => {
+                if let Some(text) = djot_text(&event) {
+                    alt.push_str(text);
+                }
+            }
+            (None, event) => expanded.push(event),
+        }
+    }
+
+    expanded.into_iter()
+}
+
+/// The target a hoisted gemtext image line points at before it is resolved.
+pub(crate) fn gemtext_target(src: &str) -> String {
+    format!("image:{}", src)
+}
+
+/// Resolves `=> image:src` lines in gemtext to the url `resolve` gives for
+/// the image.
+pub(crate) fn resolve_gemtext_images<E>(
+    gemtext: &str,
+    mut resolve: impl FnMut(&str) -> Result<String, E>,
+) -> Result<String, E> {
+    let mut resolved = String::new();
+    for line in gemtext.split_inclusive('\n') {
+        let link = match line.strip_prefix("=> image:") {
+            Some(link) => link.trim_end(),
+            None => {
+                resolved.push_str(line);
+                continue;
+            }
+        };
+        match link.split_once(' ') {
+            Some((src, label)) => resolved.push_str(&format!("=> {} {}", resolve(src)?, label)),
+            None => resolved.push_str(&format!("=> {}", resolve(link)?)),
+        }
+        if line.ends_with('\n') {
+            resolved.push('\n');
+        }
+    }
+
+    Ok(resolved)
+}
+
+pub(crate) fn placeholder(reference: &ImageReference) -> String {
+    // serializing a struct of strings can't fail
+    let json = serde_json::to_string(reference).unwrap();
+    format!(
+        "{}{}{}",
+        PLACEHOLDER_OPEN,
+        Base64Url::encode_string(json.as_bytes()),
+        PLACEHOLDER_CLOSE
+    )
+}
+
+/// Replaces every image placeholder in `html` with the output of `resolve`.
+pub(crate) fn resolve_images<E>(
+    html: &str,
+    mut resolve: impl FnMut(&ImageReference) -> Result<String, E>,
+    malformed: impl Fn(&str) -> E,
+) -> Result<String, E> {
+    let mut resolved = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find(PLACEHOLDER_OPEN) {
+        resolved.push_str(&rest[..start]);
+        let inner = &rest[start + PLACEHOLDER_OPEN.len()..];
+        let end = inner
+            .find(PLACEHOLDER_CLOSE)
+            .ok_or_else(|| malformed(inner))?;
+        let reference = Base64Url::decode_vec(&inner[..end])
+            .ok()
+            .and_then(|json| serde_json::from_slice::<ImageReference>(&json).ok())
+            .ok_or_else(|| malformed(inner))?;
+        resolved.push_str(&resolve(&reference)?);
+        rest = &inner[end + PLACEHOLDER_CLOSE.len()..];
+    }
+    resolved.push_str(rest);
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::exif_orientation;
+
+    #[test]
+    fn test_exif_orientation() {
+        let tiff: &[u8] = &[
+            b'I', b'I', 42, 0, 8, 0, 0, 0, // little endian, IFD0 at 8
+            1, 0, // one entry
+            0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, // orientation: SHORT 6
+        ];
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1, 0, (2 + 6 + tiff.len()) as u8];
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(tiff);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 2]);
+        assert_eq!(exif_orientation(&jpeg), Some(6));
+        assert_eq!(exif_orientation(&[0xFF, 0xD8, 0xFF, 0xDA, 0, 2]), None);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::files::{
+    ensure_directory, get_paths_from_glob, path_to_string, read_file_contents, write_file_contents,
+    Error as FilesError,
+};
+use crate::parsing::{parse_front_matter, Error as ParsingError};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Couldn't find markdown files in '{path}'")]
+    SearchError { source: FilesError, path: String },
+
+    #[error("Couldn't read '{path}'")]
+    ReadError { source: FilesError, path: String },
+
+    #[error("Couldn't import '{path}'")]
+    ParseError { source: ParsingError, path: String },
+
+    #[error("Couldn't write '{path}'")]
+    WriteError { source: FilesError, path: String },
+}
+
+static MARKDOWN_EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+/// Converts front matter markdown (Hugo, Jekyll or Zola posts) into post
+/// block files in `output_dir`, named by slug. `paths` are files, or
+/// directories that are searched for markdown. Existing block files are
+/// left alone unless `force` is set.
+///
+/// Returns the paths of the block files that were written.
+pub(crate) fn import_posts(
+    paths: &[String],
+    output_dir: &str,
+    author: Option<&str>,
+    force: bool,
+) -> Result<Vec<String>, Error> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        if !Path::new(path).is_dir() {
+            files.push(PathBuf::from(path));
+            continue;
+        }
+        let pattern = format!("{}/**/*.*", path);
+        let found = get_paths_from_glob(&pattern).map_err(|e| Error::SearchError {
+            source: e,
+            path: path.clone(),
+        })?;
+        files.extend(found.into_iter().filter(|file| {
+            file.extension()
+                .and_then(|extension| extension.to_str())
+                .is_some_and(|extension| MARKDOWN_EXTENSIONS.contains(&extension))
+        }));
+    }
+
+    ensure_directory(output_dir).map_err(|e| Error::WriteError {
+        source: e,
+        path: String::from(output_dir),
+    })?;
+
+    let mut written = Vec::new();
+    for file in files {
+        let name = path_to_string(&file);
+        let contents = read_file_contents(&file).map_err(|e| Error::ReadError {
+            source: e,
+            path: name.clone(),
+        })?;
+        let imported = parse_front_matter(&contents, &file_stem(&file), author).map_err(|e| {
+            Error::ParseError {
+                source: e,
+                path: name.clone(),
+            }
+        })?;
+
+        let slug = imported.post["slug"].as_str().unwrap_or_default().trim();
+        let output_path = format!("{}/{}.post", output_dir, slug);
+        if Path::new(&output_path).exists() && !force {
+            println!("skipping {} -> {} (already exists)", name, output_path);
+            continue;
+        }
+        write_file_contents(&imported.to_blocks(), &output_path).map_err(|e| {
+            Error::WriteError {
+                source: e,
+                path: output_path.clone(),
+            }
+        })?;
+        println!("importing {} -> {}", name, output_path);
+        if !imported.unmapped.is_empty() {
+            println!("  dropped fields: {}", imported.unmapped.join(", "));
+        }
+        written.push(output_path);
+    }
+
+    Ok(written)
+}
+
+/// Hugo page bundles keep their post in `slug/index.md`.
+fn file_stem(path: &Path) -> String {
+    let stem = path.file_stem().map(path_to_string).unwrap_or_default();
+    match stem.as_str() {
+        "index" | "_index" => path
+            .parent()
+            .and_then(|parent| parent.file_name())
+            .map(path_to_string)
+            .unwrap_or(stem),
+        _ => stem,
+    }
+}
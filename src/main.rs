@@ -4,19 +4,55 @@
 ///
 /// to use:
 /// `$ site-builder -s {$SOURCE_FILE_DIRECTORY} -d {$OUTPUT_DIRECTORY}`
+/// `$ site-builder -s {$SOURCE_FILE_DIRECTORY} -d {$OUTPUT_DIRECTORY} export-epub --section {$NAME}`
+/// `$ site-builder -s {$SOURCE_FILE_DIRECTORY} -d {$OUTPUT_DIRECTORY} import {$MARKDOWN_FILES}`
+/// `$ site-builder -s {$SOURCE_FILE_DIRECTORY} -d {$OUTPUT_DIRECTORY} fmt [--check]`
+/// `$ site-builder -s {$SOURCE_FILE_DIRECTORY} -d {$OUTPUT_DIRECTORY} new post {$TITLE}`
 /// `$ site-builder --help`
 ///
+mod api;
+mod assets;
+mod compress;
+mod data;
+mod epub;
+mod feeds;
 mod files;
+mod format;
+mod images;
+mod import;
+mod minify;
+mod navigation;
 mod parsing;
+mod profiles;
 mod rendering;
-
+mod scaffold;
+
+use crate::api::{export_api, resolve_strings};
+use crate::assets::collect_assets;
+use crate::compress::precompress_output;
+use crate::data::load_data;
+use crate::epub::{collect_book, export_epub, EpubSelection, CHAPTER_BASE_URL};
+use crate::feeds::{collect_feeds, export_json_feed};
 use crate::files::*;
-use crate::parsing::{parse_blocks_file, parse_json_file, Content, Page, Post, SiteContext};
-use crate::rendering::{RenderDestination, Renderer};
+use crate::format::format_content;
+use crate::images::process_images;
+use crate::import::import_posts;
+use crate::minify::minify_css_files;
+use crate::navigation::{
+    build_backlinks, build_navigation, capsule_path, entry_site_path, post_site_path,
+    section_index_site_path,
+};
+use crate::parsing::{
+    parse_blocks_file, parse_json_file, Content, Entry, Error as ParsingError, Page, Post,
+    SiteContext,
+};
+use crate::profiles::{apply_profile, BuildProfile, DEFAULT_PROFILE, PROFILE_ENV_VAR};
+use crate::rendering::{RenderDestination, RenderFormat, Renderer};
+use crate::scaffold::{create_content, NewContent};
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use rendering::RenderPassDescriptor;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Parser)]
 #[clap(name = "site-builer")]
@@ -28,26 +64,108 @@ struct Args {
     #[clap(short, long)]
     source: String,
 
-    /// Directory where the site is built to
+    /// Directory where the site is built to; not needed by fmt, import and new
     #[clap(short, long)]
-    destination: String,
+    destination: Option<String>,
 
     /// Path to config.json file
     #[clap(short, long)]
     config: Option<String>,
 
+    /// Directory where a gemini capsule is built to, alongside the site
+    #[clap(long)]
+    capsule: Option<String>,
+
     /// Build the site in debug mode
     #[clap(long)]
     debug: bool,
+
+    /// Minify exported html and copied css, whatever the profile says
+    #[clap(long)]
+    minify: bool,
+
+    /// Build profile from config.json [env: SITE_BUILDER_PROFILE] [default: dev]
+    #[clap(long)]
+    profile: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Bundle the posts of a section or series into an EPUB 3 book
+    ExportEpub {
+        /// Name of the section to bundle
+        #[clap(long, conflicts_with = "series", required_unless_present = "series")]
+        section: Option<String>,
+
+        /// Name of the series to bundle
+        #[clap(long)]
+        series: Option<String>,
+
+        /// Path of the .epub file [default: {destination}/{name}.epub]
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+
+    /// Convert front matter markdown (Hugo, Jekyll, Zola) into post block files
+    Import {
+        /// Markdown files, or directories to search for them
+        #[clap(required = true)]
+        paths: Vec<String>,
+
+        /// Directory the block files are written to [default: {source}/content/posts]
+        #[clap(short, long)]
+        output: Option<String>,
+
+        /// Author for posts whose front matter doesn't name one
+        #[clap(long)]
+        author: Option<String>,
+
+        /// Overwrite block files that already exist
+        #[clap(long)]
+        force: bool,
+    },
+
+    /// Format content block files in place
+    Fmt {
+        /// Only report files that aren't formatted, and fail if there are any
+        #[clap(long)]
+        check: bool,
+    },
+
+    /// Create a block file from the archetype of a content type
+    New {
+        /// Content type: post, page, or one declared in config.json
+        content_type: String,
+
+        /// Title, which the slug is made from
+        title: String,
+
+        /// Directory under content/, also set as the content's output directory
+        /// [default: posts, pages, or the type's directory, leaving the output
+        /// directory unset]
+        directory: Option<String>,
+
+        /// Author [default: author_name in config.json]
+        #[clap(long)]
+        author: Option<String>,
+    },
 }
 
 struct BuildConfig {
     debug: bool,
+    profile: BuildProfile,
     source_dir_path: String,
     output_dir_path: String,
-    config_file_path: String,
+    capsule_dir_path: Option<String>,
     content_dir_path: String,
+    archetypes_dir_path: String,
     css_dir_path: String,
+    data_dir_path: String,
+    images_dir_path: String,
+    cache_dir_path: String,
     content_glob: String,
     components_glob: String,
     templates_glob: String,
@@ -57,16 +175,45 @@ struct SiteConfig {
     context: SiteContext,
     posts: HashMap<String, Post>,
     pages: HashMap<String, Page>,
+    entries: HashMap<String, Entry>,
+    data: serde_json::Value,
 }
 
 static DEFAULT_CONFIG_PATH: &str = "config.json";
 
-fn create_build_config(args: Args) -> Result<BuildConfig> {
+/// Builds the configuration from the CLI args, along with config.json as the
+/// selected profile overlays it.
+///
+/// The output directories (and the css they're built from) are only checked
+/// when `command` builds the site or exports from it; `fmt`, `import` and
+/// `new` only work on the source.
+fn create_build_config(
+    args: Args,
+    command: &Option<Command>,
+) -> Result<(BuildConfig, serde_json::Value)> {
+    let builds_output = !matches!(
+        command,
+        Some(Command::Fmt { .. } | Command::Import { .. } | Command::New { .. })
+    );
+
     let source_dir_path = args.source;
     expect_directory(&source_dir_path).context(r"Missing expected {source} directory")?;
 
-    let output_dir_path = args.destination;
-    ensure_directory(&output_dir_path).context(r"Couldn't create {output} directory")?;
+    let output_dir_path = match builds_output {
+        true => {
+            let output_dir_path = args
+                .destination
+                .context(r"Missing {destination} directory, which the site is built to")?;
+            ensure_directory(&output_dir_path).context(r"Couldn't create {output} directory")?;
+            output_dir_path
+        }
+        false => args.destination.unwrap_or_default(),
+    };
+
+    let capsule_dir_path = args.capsule;
+    if let (Some(capsule_dir_path), true) = (&capsule_dir_path, builds_output) {
+        ensure_directory(capsule_dir_path).context(r"Couldn't create {capsule} directory")?;
+    }
 
     let config_file_path = match args.config {
         Some(user_given_config_path) => user_given_config_path.to_owned(),
@@ -78,90 +225,306 @@ fn create_build_config(args: Args) -> Result<BuildConfig> {
     };
     expect_file(&config_file_path).context("Missing expected config.json file")?;
 
+    let profile_name = args
+        .profile
+        .or_else(|| std::env::var(PROFILE_ENV_VAR).ok())
+        .unwrap_or_else(|| String::from(DEFAULT_PROFILE));
+    let (config, mut profile) = load_config(&config_file_path, &profile_name)?;
+    profile.minify |= args.minify;
+
     let content_dir_path = format!("{src}/content", src = source_dir_path);
     expect_directory(&content_dir_path).context(r"Missing expected {src}/content directory")?;
 
+    let archetypes_dir_path = format!("{src}/archetypes", src = source_dir_path);
+
     let css_dir_path = format!("{src}/css", src = source_dir_path);
-    expect_directory(&css_dir_path).context(r"Missing expected {src}/css directory")?;
+    if builds_output {
+        expect_directory(&css_dir_path).context(r"Missing expected {src}/css directory")?;
+    }
+
+    let data_dir_path = format!("{src}/data", src = source_dir_path);
+    let images_dir_path = format!("{src}/static/images", src = source_dir_path);
+    let cache_dir_path = format!("{src}/.cache", src = source_dir_path);
 
     let content_glob = format!("{cnt}/**/*.*", cnt = content_dir_path);
     let templates_glob = format!("{src}/templates/**/*.tmpl", src = source_dir_path);
     let components_glob = format!("{src}/components/**/*", src = source_dir_path);
 
-    Ok(BuildConfig {
+    let build_config = BuildConfig {
         debug: args.debug,
+        profile,
         source_dir_path,
-        config_file_path,
         output_dir_path,
+        capsule_dir_path,
         content_dir_path,
+        archetypes_dir_path,
         css_dir_path,
+        data_dir_path,
+        images_dir_path,
+        cache_dir_path,
         content_glob,
         templates_glob,
         components_glob,
-    })
+    };
+
+    Ok((build_config, config))
 }
 
-fn create_site_config(
+/// Loads config.json, overlaid by the selected profile.
+fn load_config(
     path: impl AsRef<std::path::Path>,
-    pages: HashMap<String, Page>,
-    posts: HashMap<String, Post>,
-) -> Result<SiteConfig> {
-    let raw_context: SiteContext =
+    profile_name: &str,
+) -> Result<(serde_json::Value, BuildProfile)> {
+    let mut config: serde_json::Value =
         parse_json_file(path).context("Couldn't load config.json file")?;
+    let profile = apply_profile(&mut config, profile_name)
+        .context(format!("Couldn't apply build profile '{}'", profile_name))?;
+    Ok((config, profile))
+}
 
-    let context = raw_context.clone();
+fn create_site_config(config: serde_json::Value, data: serde_json::Value) -> Result<SiteConfig> {
+    let context: SiteContext =
+        serde_json::from_value(config).context("Couldn't parse config.json file")?;
 
     Ok(SiteConfig {
         context,
-        pages,
-        posts,
+        pages: HashMap::new(),
+        posts: HashMap::new(),
+        entries: HashMap::new(),
+        data,
     })
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    let command = args.command.take();
 
     if args.debug {
         println!("\n================== Begin Site Builder ==================\n");
     }
 
     // build config struct
-    let build_config = create_build_config(args)
+    let (build_config, config) = create_build_config(args, &command)
         .context("Failed to create a build configuration from CLI args")?;
 
+    // import and fmt only work on files, so they don't need the rest of the site
+    let command = match command {
+        Some(Command::Import {
+            paths,
+            output,
+            author,
+            force,
+        }) => {
+            let output =
+                output.unwrap_or_else(|| format!("{}/posts", build_config.content_dir_path));
+            import_posts(&paths, &output, author.as_deref(), force)
+                .context("Failed to import markdown files")?;
+            return Ok(());
+        }
+        Some(Command::Fmt { check }) => {
+            format_content(&build_config.content_glob, check)
+                .context("Failed to format content")?;
+            return Ok(());
+        }
+        command => command,
+    };
+
+    // load global data files
+    let data = load_data(&build_config.data_dir_path).context("Failed to load data files")?;
+
+    // build site config
+    let mut site_config = create_site_config(config, data)
+        .context("Failed to create a site configuration from config file")?;
+    let content_types = site_config
+        .context
+        .content_types
+        .clone()
+        .unwrap_or_default();
+
+    // new only needs the config, to know the content types and author
+    let command = match command {
+        Some(Command::New {
+            content_type,
+            title,
+            directory,
+            author,
+        }) => {
+            let new = NewContent {
+                content_type: &content_type,
+                title: &title,
+                directory: directory.as_deref(),
+                author: author
+                    .as_deref()
+                    .or(site_config.context.author_name.as_deref()),
+            };
+            let path = create_content(
+                &new,
+                &build_config.content_dir_path,
+                &build_config.archetypes_dir_path,
+                &content_types,
+            )
+            .context(format!("Failed to create new '{}'", content_type))?;
+            println!("created {}", path);
+            return Ok(());
+        }
+        command => command,
+    };
+
     // load all content
-    let mut posts: HashMap<String, Post> = HashMap::new();
-    let mut pages: HashMap<String, Page> = HashMap::new();
     let content_paths = get_paths_from_glob(&build_config.content_glob)
         .context("Failed to resolve content path glob")?;
+    let mut included_paths = HashSet::new();
+    let mut untyped_paths = Vec::new();
     for path in content_paths {
         let content_name = get_stripped_base_path_string(&path, &build_config.content_dir_path)
             .context("Failed to strip content path prefix")?;
 
-        match parse_blocks_file(&path)
-            .context(format!("Failed to parse block file: {:?}", &path))?
-        {
+        let content = match parse_blocks_file(&path, &content_types) {
+            // files without a type may only be there to be included
+            Err(ParsingError::MissingTypeDeclarationError) => {
+                untyped_paths.push(path);
+                continue;
+            }
+            content => content.context(format!("Failed to parse block file: {:?}", &path))?,
+        };
+        for source in content.sources() {
+            included_paths.extend(source.includes.iter().cloned());
+        }
+        match content {
             Content::Post(post) => {
-                if post.metadata.build {
-                    posts.insert(content_name, post);
+                if post.metadata.build || build_config.profile.drafts {
+                    site_config.posts.insert(content_name, post);
                 }
             }
             Content::Page(page) => {
-                if page.metadata.build {
-                    pages.insert(content_name, page);
+                if page.metadata.build || build_config.profile.drafts {
+                    site_config.pages.insert(content_name, page);
+                }
+            }
+            Content::Entry(mut entry) => {
+                if !entry.build && !build_config.profile.drafts {
+                    continue;
+                }
+                if let Some(content_type) = content_types
+                    .iter()
+                    .find(|content_type| content_type.name == entry.content_type)
+                {
+                    entry.url = Some(entry_site_path(content_type, &entry));
                 }
+                site_config.entries.insert(content_name, entry);
             }
         };
     }
+    for path in untyped_paths {
+        let canonical_path = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if !included_paths.contains(&path_to_string(&canonical_path)) {
+            return Err(ParsingError::MissingTypeDeclarationError)
+                .context(format!("Failed to parse block file: {:?}", &path));
+        }
+    }
 
-    // build site config
-    let site_config = create_site_config(&build_config.config_file_path, pages, posts)
-        .context("Failed to create a site configuration from config file")?;
+    // link posts to their neighbours and series
+    let navigation = build_navigation(&site_config.posts);
+
+    // process images
+    let images = process_images(
+        &build_config.images_dir_path,
+        &build_config.output_dir_path,
+        &site_config.context.images.clone().unwrap_or_default(),
+        build_config.debug,
+    )
+    .context("Failed to process images")?;
+
+    // copy over css
+    let css_out_path = format!("{}/css", &build_config.output_dir_path);
+    dircpy::copy_dir_advanced(
+        &build_config.css_dir_path,
+        &css_out_path,
+        true,
+        false,
+        false,
+        vec![],
+        vec![],
+    )
+    .expect("css failed to copy");
+    if build_config.profile.minify {
+        minify_css_files(&css_out_path, build_config.debug).context("Failed to minify css")?;
+    }
+    let assets = collect_assets(
+        &build_config.css_dir_path,
+        &build_config.output_dir_path,
+        "css",
+        build_config.profile.fingerprint,
+    )
+    .context("Failed to fingerprint css")?;
 
     // create renderer
-    let mut renderer = Renderer::new(&build_config, &site_config)
+    let mut renderer = Renderer::new(&build_config, &site_config, images, assets)
         .context("Failed to create a site template renderer")?;
 
+    // register every url up front so wikilinks resolve regardless of render order
+    let mut page_urls: HashMap<String, String> = HashMap::new();
+    for section in &site_config.context.sections {
+        if site_config.pages.contains_key(&section.index_content) {
+            page_urls.insert(
+                section.index_content.clone(),
+                section_index_site_path(section),
+            );
+        }
+    }
+    for (name, url) in &page_urls {
+        renderer.register_page_url(name, url.clone());
+        if build_config.capsule_dir_path.is_some() {
+            renderer.register_page_capsule_url(name, capsule_path(url));
+        }
+    }
+    for (name, post) in &site_config.posts {
+        renderer.register_post_url(name, post_site_path(post));
+        if build_config.capsule_dir_path.is_some() {
+            renderer.register_post_capsule_url(name, capsule_path(&post_site_path(post)));
+        }
+    }
+    let backlinks = build_backlinks(
+        &site_config.posts,
+        &site_config.pages,
+        &site_config.entries,
+        &page_urls,
+    );
+
+    // export an epub instead of building the site
+    if let Some(Command::ExportEpub {
+        section,
+        series,
+        output,
+    }) = command
+    {
+        let selection = match (section, series) {
+            (_, Some(series)) => EpubSelection::Series(series),
+            (section, None) => EpubSelection::Section(section.unwrap_or_default()),
+        };
+        let book = collect_book(&site_config.context, &site_config.posts, &selection)
+            .context("Failed to collect posts for the epub")?;
+        let mut chapter_html: HashMap<String, String> = HashMap::new();
+        for (name, post) in &book.posts {
+            let html = renderer
+                .render_fragment(&post.html, post, CHAPTER_BASE_URL)
+                .context(format!("Failed to render epub chapter '{}'", &name))?;
+            chapter_html.insert((*name).clone(), html);
+        }
+        let path = output.unwrap_or_else(|| {
+            format!("{}/{}.epub", build_config.output_dir_path, book.file_stem())
+        });
+        export_epub(
+            &path,
+            &build_config.output_dir_path,
+            &site_config.context,
+            &book,
+            &chapter_html,
+        )
+        .context(format!("Failed to export epub '{}'", book.name))?;
+        return Ok(());
+    }
+
     // build sitemap
     for section in &site_config.context.sections {
         let section_path = format!("{}/{}", build_config.output_dir_path, section.site_path);
@@ -177,7 +540,10 @@ fn main() -> Result<()> {
         let desc = RenderPassDescriptor {
             render_name: name.clone(),
             base_template: "post.tmpl",
+            format: RenderFormat::Html,
             context: &post,
+            navigation: navigation.get(name).cloned(),
+            backlinks: backlinks.get(name).cloned().unwrap_or_default(),
             destination: RenderDestination::Explicit {
                 directory: format!(
                     "{}/{}",
@@ -213,6 +579,7 @@ fn main() -> Result<()> {
         let desc = RenderPassDescriptor {
             render_name: section.index_content.clone(),
             base_template: "content.tmpl",
+            format: RenderFormat::Html,
             destination: RenderDestination::SectionIndex {
                 directory: section_path,
             },
@@ -223,24 +590,166 @@ fn main() -> Result<()> {
                     "Missing index page for section '{}'",
                     section.name
                 )),
+            navigation: None,
+            backlinks: backlinks
+                .get(&section.index_content)
+                .cloned()
+                .unwrap_or_default(),
         };
         renderer
             .render_content(desc)
             .context(format!("Failed to render section '{}'", &section.name))?;
     }
 
-    // copy over css
-    let css_out_path = format!("{}/css", &build_config.output_dir_path);
-    dircpy::copy_dir_advanced(
-        &build_config.css_dir_path,
-        &css_out_path,
-        true,
-        false,
-        false,
-        vec![],
-        vec![],
+    // render user-defined content types
+    for (name, entry) in &site_config.entries {
+        let content_type = content_types
+            .iter()
+            .find(|content_type| content_type.name == entry.content_type)
+            .expect(&format!("Missing content type '{}'", entry.content_type));
+        let desc = RenderPassDescriptor {
+            render_name: name.clone(),
+            base_template: &content_type.content_template,
+            format: RenderFormat::Html,
+            context: &entry,
+            navigation: None,
+            backlinks: backlinks.get(name).cloned().unwrap_or_default(),
+            destination: RenderDestination::Explicit {
+                directory: format!(
+                    "{}/{}",
+                    build_config.output_dir_path,
+                    content_type.directory()
+                ),
+                filename: entry.slug.clone(),
+            },
+        };
+        renderer
+            .render_content(desc)
+            .context(format!("Failed to render '{}'", &name))?;
+    }
+
+    // write the json content api
+    let mut api_html: HashMap<String, String> = HashMap::new();
+    for (name, post) in &site_config.posts {
+        let html = renderer
+            .render_fragment(&post.html, post, "")
+            .context(format!("Failed to render api html for '{}'", &name))?;
+        api_html.insert(name.clone(), html);
+    }
+    for (name, page) in &site_config.pages {
+        let html = renderer
+            .render_fragment(&page.html, page, "")
+            .context(format!("Failed to render api html for '{}'", &name))?;
+        api_html.insert(name.clone(), html);
+    }
+    let mut api_fields: HashMap<String, serde_json::Value> = HashMap::new();
+    for (name, entry) in &site_config.entries {
+        let mut fields = serde_json::Value::Object(entry.fields.clone());
+        resolve_strings(&mut fields, &mut |field| {
+            renderer.render_fragment(field, entry, "")
+        })
+        .context(format!("Failed to render api fields for '{}'", &name))?;
+        api_fields.insert(name.clone(), fields);
+    }
+    export_api(
+        &build_config.output_dir_path,
+        &site_config.posts,
+        &site_config.pages,
+        &site_config.entries,
+        &page_urls,
+        &api_html,
+        &api_fields,
     )
-    .expect("css failed to copy");
+    .context("Failed to write the content api")?;
+
+    // write json feeds
+    let feeds = collect_feeds(&site_config.context, &site_config.posts);
+    if !feeds.is_empty() {
+        let origin = site_config
+            .context
+            .site_url
+            .as_deref()
+            .unwrap_or("")
+            .trim_end_matches('/');
+        let mut feed_html: HashMap<String, String> = HashMap::new();
+        for (name, post) in &site_config.posts {
+            let html = renderer
+                .render_fragment(&post.html, post, origin)
+                .context(format!("Failed to render feed html for '{}'", &name))?;
+            feed_html.insert(name.clone(), html);
+        }
+        for feed in &feeds {
+            export_json_feed(
+                &build_config.output_dir_path,
+                &site_config.context,
+                feed,
+                &feed_html,
+            )
+            .context(format!("Failed to write feed '{}'", feed.site_path()))?;
+        }
+    }
+
+    // render gemini capsule
+    if let Some(capsule_dir_path) = &build_config.capsule_dir_path {
+        for (name, post) in &site_config.posts {
+            let desc = RenderPassDescriptor {
+                render_name: name.clone(),
+                base_template: "post.gmi.tmpl",
+                format: RenderFormat::Gemtext,
+                context: &post,
+                navigation: navigation.get(name).map(|nav| nav.to_capsule()),
+                backlinks: backlinks
+                    .get(name)
+                    .map(|links| links.iter().map(|link| link.to_capsule()).collect())
+                    .unwrap_or_default(),
+                destination: RenderDestination::Explicit {
+                    directory: format!("{}/{}", capsule_dir_path, post.metadata.directory),
+                    filename: post.slug.clone(),
+                },
+            };
+            renderer
+                .render_content(desc)
+                .context(format!("Failed to render '{}' to gemtext", &name))?;
+        }
+
+        for section in &site_config.context.sections {
+            let desc = RenderPassDescriptor {
+                render_name: section.index_content.clone(),
+                base_template: "content.gmi.tmpl",
+                format: RenderFormat::Gemtext,
+                destination: RenderDestination::SectionIndex {
+                    directory: format!("{}/{}", capsule_dir_path, section.site_path),
+                },
+                context: site_config
+                    .pages
+                    .get(&section.index_content)
+                    .expect(&format!(
+                        "Missing index page for section '{}'",
+                        section.name
+                    )),
+                navigation: None,
+                backlinks: backlinks
+                    .get(&section.index_content)
+                    .map(|links| links.iter().map(|link| link.to_capsule()).collect())
+                    .unwrap_or_default(),
+            };
+            renderer.render_content(desc).context(format!(
+                "Failed to render section '{}' to gemtext",
+                &section.name
+            ))?;
+        }
+    }
+
+    // write precompressed siblings
+    if let Some(compression) = &site_config.context.compression {
+        precompress_output(
+            &build_config.output_dir_path,
+            &build_config.cache_dir_path,
+            compression,
+            build_config.debug,
+        )
+        .context("Failed to precompress output")?;
+    }
 
     if build_config.debug {
         println!("\n=================== End Site Builder ===================\n");
@@ -0,0 +1,252 @@
+use crate::files::{
+    get_paths_from_glob, read_file_contents, write_file_contents, Error as FilesError,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Couldn't find css files to minify")]
+    CssSearchError { source: FilesError },
+
+    #[error("Couldn't read css file '{path}'")]
+    CssReadError { source: FilesError, path: String },
+
+    #[error("Couldn't write css file '{path}'")]
+    CssWriteError { source: FilesError, path: String },
+}
+
+// elements whose contents are copied through untouched
+static RAW_ELEMENTS: [&str; 3] = ["pre", "textarea", "script"];
+
+/// Minifies html: collapses whitespace (except inside `<pre>`, `<textarea>`
+/// and `<script>`), drops comments, and minifies inline `<style>` elements.
+///
+/// Comments starting with `<!--[` (conditional comments) or `<!--!` are kept.
+pub(crate) fn minify_html(html: &str) -> String {
+    let mut minified = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        push_collapsed(&mut minified, &rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("<!--") {
+            let end = rest.find("-->").map_or(rest.len(), |end| end + 3);
+            if rest.starts_with("<!--[") || rest.starts_with("<!--!") {
+                minified.push_str(&rest[..end]);
+            }
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = tag_end(rest);
+        let tag = &rest[..end];
+        rest = &rest[end..];
+        push_tag(&mut minified, tag);
+
+        let name = tag_name(tag);
+        let is_raw = RAW_ELEMENTS.contains(&name.as_str());
+        if tag.starts_with("</") || !(is_raw || name == "style") {
+            continue;
+        }
+        let close = format!("</{}", name);
+        let end = find_ignore_case(rest, &close).unwrap_or(rest.len());
+        match is_raw {
+            true => minified.push_str(&rest[..end]),
+            false => minified.push_str(&minify_css(&rest[..end])),
+        }
+        rest = &rest[end..];
+    }
+    push_collapsed(&mut minified, rest);
+
+    String::from(minified.trim())
+}
+
+/// Minifies css: drops comments (except `/*! ... */`) and any whitespace
+/// that isn't needed to separate tokens.
+pub(crate) fn minify_css(css: &str) -> String {
+    let mut minified = String::with_capacity(css.len());
+    let mut pending_space = false;
+    let mut chars = css.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let keep = chars.peek() == Some(&'!');
+                let mut comment = String::from("/*");
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    comment.push(c);
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+                match keep {
+                    true => minified.push_str(&comment),
+                    false => pending_space = true,
+                }
+            }
+            c if c.is_whitespace() => pending_space = true,
+            c => {
+                if pending_space
+                    && !minified.is_empty()
+                    && !minified.ends_with(['{', '}', ';', ',', '>', ':', '('])
+                    && !['{', '}', ';', ',', '>', ')'].contains(&c)
+                {
+                    minified.push(' ');
+                }
+                pending_space = false;
+                if c == '}' && minified.ends_with(';') {
+                    minified.pop();
+                }
+                minified.push(c);
+
+                // strings are copied through untouched
+                if c == '"' || c == '\'' {
+                    let mut escaped = false;
+                    for s in chars.by_ref() {
+                        minified.push(s);
+                        match s {
+                            '\\' if !escaped => escaped = true,
+                            s if s == c && !escaped => break,
+                            _ => escaped = false,
+                        }
+                    }
+                }
+            }
+        }
+    }
+    minified
+}
+
+/// Minifies every css file under `directory` in place.
+pub(crate) fn minify_css_files(directory: &String, log: bool) -> Result<(), Error> {
+    let pattern = format!("{}/**/*.css", directory);
+    let paths = get_paths_from_glob(&pattern).map_err(|e| Error::CssSearchError { source: e })?;
+    for path in paths {
+        let path = path.to_string_lossy().to_string();
+        let css = read_file_contents(&path).map_err(|e| Error::CssReadError {
+            source: e,
+            path: path.clone(),
+        })?;
+        let minified = minify_css(&css);
+        if log {
+            report(&path, css.len(), minified.len());
+        }
+        write_file_contents(&minified, &path).map_err(|e| Error::CssWriteError {
+            source: e,
+            path: path.clone(),
+        })?;
+    }
+    Ok(())
+}
+
+pub(crate) fn report(name: &str, before: usize, after: usize) {
+    println!(
+        "minified {}: {} -> {} bytes ({} saved)",
+        name,
+        before,
+        after,
+        before.saturating_sub(after)
+    );
+}
+
+fn push_collapsed(minified: &mut String, text: &str) {
+    for c in text.chars() {
+        match c.is_whitespace() {
+            true if minified.ends_with(' ') => {}
+            true => minified.push(' '),
+            false => minified.push(c),
+        }
+    }
+}
+
+/// Pushes a tag with the whitespace between its attributes collapsed; quoted
+/// attribute values are left alone.
+fn push_tag(minified: &mut String, tag: &str) {
+    let mut quote = None;
+    let mut pending_space = false;
+    for c in tag.chars() {
+        match quote {
+            Some(q) => {
+                minified.push(c);
+                if c == q {
+                    quote = None;
+                }
+            }
+            None if c.is_whitespace() => pending_space = true,
+            None => {
+                if pending_space && c != '>' {
+                    minified.push(' ');
+                }
+                pending_space = false;
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                }
+                minified.push(c);
+            }
+        }
+    }
+}
+
+/// The end of the tag at the start of `html`, past its closing `>`.
+fn tag_end(html: &str) -> usize {
+    let mut quote = None;
+    for (index, c) in html.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return index + 1,
+            _ => {}
+        }
+    }
+    html.len()
+}
+
+fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches('<')
+        .trim_start_matches('/')
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .next()
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .to_ascii_lowercase()
+        .find(&needle.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{minify_css, minify_html};
+
+    #[test]
+    fn test_html_collapses_whitespace_but_not_pre() {
+        let html = "<!DOCTYPE html>\n<html>\n  <body class=\"a  b\"\n    id=x>\n    <p>Hello,\n      <em>world</em>  !</p>\n    <!-- note -->\n    <pre>  keep\n    this</pre>\n  </body>\n</html>\n";
+        assert_eq!(
+            minify_html(html),
+            "<!DOCTYPE html> <html> <body class=\"a  b\" id=x> <p>Hello, <em>world</em> !</p> <pre>  keep\n    this</pre> </body> </html>"
+        );
+    }
+
+    #[test]
+    fn test_html_keeps_conditional_comments_and_minifies_style() {
+        let html = "<!--[if IE]>x<![endif]--><style>\n  p {\n    color: red;\n  }\n</style>";
+        assert_eq!(
+            minify_html(html),
+            "<!--[if IE]>x<![endif]--><style>p{color:red}</style>"
+        );
+    }
+
+    #[test]
+    fn test_css_keeps_needed_spaces() {
+        let css = "/* c */\n@media (min-width: 10px) and (max-width: 20px) {\n  div :first-child > a,\n  b { content: \"a  b\"; width: calc(1px + 2px); }\n}\n/*! keep */\n";
+        assert_eq!(
+            minify_css(css),
+            "@media (min-width:10px) and (max-width:20px){div :first-child>a,b{content:\"a  b\";width:calc(1px + 2px)}}/*! keep */"
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use crate::parsing::{collect_wikilinks, Entry, Page, Post, SiteContentType, SiteSection};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct NavigationLink {
+    pub name: String,
+    pub title: String,
+    pub url: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct SeriesNavigation {
+    pub name: String,
+    pub position: usize,
+    pub previous: Option<NavigationLink>,
+    pub next: Option<NavigationLink>,
+    pub parts: Vec<NavigationLink>,
+}
+
+#[derive(Serialize, Clone, Debug, Default)]
+pub(crate) struct Navigation {
+    pub previous: Option<NavigationLink>,
+    pub next: Option<NavigationLink>,
+    pub series: Option<SeriesNavigation>,
+}
+
+impl NavigationLink {
+    /// The same link, pointing into the gemini capsule.
+    pub(crate) fn to_capsule(&self) -> NavigationLink {
+        NavigationLink {
+            url: capsule_path(&self.url),
+            ..self.clone()
+        }
+    }
+}
+impl Navigation {
+    /// The same navigation, pointing into the gemini capsule.
+    pub(crate) fn to_capsule(&self) -> Navigation {
+        let link = |link: &Option<NavigationLink>| link.as_ref().map(|link| link.to_capsule());
+        Navigation {
+            previous: link(&self.previous),
+            next: link(&self.next),
+            series: self.series.as_ref().map(|series| SeriesNavigation {
+                name: series.name.clone(),
+                position: series.position,
+                previous: link(&series.previous),
+                next: link(&series.next),
+                parts: series.parts.iter().map(|part| part.to_capsule()).collect(),
+            }),
+        }
+    }
+}
+
+/// Builds prev/next links for every post.
+///
+/// Chronological links are scoped to the post's `directory`, series links
+/// are ordered by `series_order` (falling back to `published_date`).
+pub(crate) fn build_navigation(posts: &HashMap<String, Post>) -> HashMap<String, Navigation> {
+    let mut navigation: HashMap<String, Navigation> = posts
+        .keys()
+        .map(|name| (name.clone(), Navigation::default()))
+        .collect();
+
+    // chronological, per directory
+    let mut directories: HashMap<&str, Vec<(&String, &Post)>> = HashMap::new();
+    for (name, post) in posts {
+        directories
+            .entry(post.metadata.directory.as_str())
+            .or_default()
+            .push((name, post));
+    }
+    for (_, mut entries) in directories {
+        entries.sort_by(|(a_name, a), (b_name, b)| {
+            (&a.metadata.published_date, a_name).cmp(&(&b.metadata.published_date, b_name))
+        });
+        for (i, (name, _)) in entries.iter().enumerate() {
+            let nav = navigation.get_mut(*name).unwrap();
+            nav.previous = i.checked_sub(1).map(|i| link(entries[i].0, entries[i].1));
+            nav.next = entries.get(i + 1).map(|(name, post)| link(name, post));
+        }
+    }
+
+    // ordered, per series
+    let mut series: HashMap<&str, Vec<(&String, &Post)>> = HashMap::new();
+    for (name, post) in posts {
+        if let Some(series_name) = &post.metadata.series {
+            series
+                .entry(series_name.as_str())
+                .or_default()
+                .push((name, post));
+        }
+    }
+    for (series_name, mut entries) in series {
+        entries.sort_by(|(a_name, a), (b_name, b)| {
+            let a_key = (
+                a.metadata.series_order.unwrap_or(u32::MAX),
+                &a.metadata.published_date,
+                a_name,
+            );
+            let b_key = (
+                b.metadata.series_order.unwrap_or(u32::MAX),
+                &b.metadata.published_date,
+                b_name,
+            );
+            a_key.cmp(&b_key)
+        });
+        let parts: Vec<NavigationLink> = entries
+            .iter()
+            .map(|(name, post)| link(name, post))
+            .collect();
+        for (i, (name, _)) in entries.iter().enumerate() {
+            let nav = navigation.get_mut(*name).unwrap();
+            nav.series = Some(SeriesNavigation {
+                name: String::from(series_name),
+                position: i + 1,
+                previous: i.checked_sub(1).map(|i| parts[i].clone()),
+                next: parts.get(i + 1).cloned(),
+                parts: parts.clone(),
+            });
+        }
+    }
+
+    navigation
+}
+
+/// Builds the reverse index of wikilinks: for every post, page and entry, the
+/// rendered content that links to it.
+pub(crate) fn build_backlinks(
+    posts: &HashMap<String, Post>,
+    pages: &HashMap<String, Page>,
+    entries: &HashMap<String, Entry>,
+    page_urls: &HashMap<String, String>,
+) -> HashMap<String, Vec<NavigationLink>> {
+    let names: Vec<&String> = posts
+        .keys()
+        .chain(pages.keys())
+        .chain(entries.keys())
+        .collect();
+    let entry_html: HashMap<&String, String> = entries
+        .iter()
+        .map(|(name, entry)| (name, entry.html()))
+        .collect();
+    let sources = posts
+        .iter()
+        .map(|(name, post)| (name, &post.html, link(name, post)))
+        .chain(pages.iter().filter_map(|(name, page)| {
+            let url = page_urls.get(name)?;
+            let link = NavigationLink {
+                name: name.clone(),
+                title: page.title.clone(),
+                url: url.clone(),
+            };
+            Some((name, &page.html, link))
+        }))
+        .chain(entries.iter().filter_map(|(name, entry)| {
+            let link = NavigationLink {
+                name: name.clone(),
+                title: String::from(entry.title()),
+                url: entry.url.clone()?,
+            };
+            Some((name, &entry_html[name], link))
+        }));
+
+    let mut backlinks: HashMap<String, Vec<NavigationLink>> = HashMap::new();
+    for (source, html, source_link) in sources {
+        let mut targets: Vec<&String> = collect_wikilinks(html)
+            .iter()
+            .filter_map(|target| {
+                names
+                    .iter()
+                    .find(|name| name.as_str() == target)
+                    .or_else(|| names.iter().find(|name| matches_content_name(name, target)))
+                    .copied()
+            })
+            .filter(|target| *target != source)
+            .collect();
+        targets.sort();
+        targets.dedup();
+        for target in targets {
+            backlinks
+                .entry(target.clone())
+                .or_default()
+                .push(source_link.clone());
+        }
+    }
+    for links in backlinks.values_mut() {
+        links.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    backlinks
+}
+
+/// Whether `name` refers to the content registered as `key`, either exactly
+/// or without its file extension.
+pub(crate) fn matches_content_name(key: &str, name: &str) -> bool {
+    if key == name {
+        return true;
+    }
+    match key.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains('/') => stem == name,
+        _ => false,
+    }
+}
+
+/// The site-scoped path a section's index page is exported to.
+pub(crate) fn section_index_site_path(section: &SiteSection) -> String {
+    match section.site_path.as_str() {
+        "" => String::from("index.html"),
+        site_path => format!("{}/index.html", site_path),
+    }
+}
+
+/// The site-scoped path a post will be exported to.
+pub(crate) fn post_site_path(post: &Post) -> String {
+    match post.metadata.directory.as_str() {
+        "" => format!("{}.html", post.slug),
+        directory => format!("{}/{}.html", directory, post.slug),
+    }
+}
+
+/// The site-scoped path an entry of a user-defined type is rendered to.
+pub(crate) fn entry_site_path(content_type: &SiteContentType, entry: &Entry) -> String {
+    match content_type.directory().as_str() {
+        "" => format!("{}.html", entry.slug),
+        directory => format!("{}/{}.html", directory, entry.slug),
+    }
+}
+
+/// Maps a site-scoped html path to its gemini capsule counterpart.
+pub(crate) fn capsule_path(site_path: &str) -> String {
+    match site_path.strip_suffix(".html") {
+        Some(stem) => format!("{}.gmi", stem),
+        None => String::from(site_path),
+    }
+}
+
+fn link(name: &str, post: &Post) -> NavigationLink {
+    NavigationLink {
+        name: String::from(name),
+        title: post.title.clone(),
+        url: post_site_path(post),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_backlinks, build_navigation, matches_content_name};
+    use crate::parsing::{Page, Post};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn post(directory: &str, date: &str, series: Option<(&str, u32)>) -> Post {
+        let mut metadata = json!({
+            "directory": directory,
+            "author_name": "author",
+            "published_date": date,
+        });
+        if let Some((series, order)) = series {
+            metadata["series"] = json!(series);
+            metadata["series_order"] = json!(order);
+        }
+        Post::try_from(json!({
+            "metadata": metadata,
+            "title": "title",
+            "slug": date,
+            "content": "",
+        }))
+        .unwrap()
+    }
+
+    fn posts(posts: Vec<(&str, Post)>) -> HashMap<String, Post> {
+        posts
+            .into_iter()
+            .map(|(name, post)| (String::from(name), post))
+            .collect()
+    }
+
+    #[test]
+    fn test_chronological_links_stay_in_their_directory() {
+        let posts = posts(vec![
+            ("a", post("notes", "2022-01-03", None)),
+            ("b", post("notes", "2022-01-01", None)),
+            ("c", post("essays", "2022-01-02", None)),
+        ]);
+        let navigation = build_navigation(&posts);
+        let name = |link: &Option<super::NavigationLink>| link.as_ref().map(|l| l.name.clone());
+
+        assert_eq!(name(&navigation["b"].previous), None);
+        assert_eq!(name(&navigation["b"].next).as_deref(), Some("a"));
+        assert_eq!(name(&navigation["a"].previous).as_deref(), Some("b"));
+        assert_eq!(name(&navigation["a"].next), None);
+        assert_eq!(name(&navigation["c"].previous), None);
+        assert_eq!(name(&navigation["c"].next), None);
+        assert_eq!(
+            navigation["a"].previous.as_ref().unwrap().url,
+            "notes/2022-01-01.html"
+        );
+    }
+
+    #[test]
+    fn test_series_follow_series_order_then_date_then_name() {
+        let posts = posts(vec![
+            ("first", post("", "2022-03-01", Some(("trip", 1)))),
+            ("second", post("", "2022-01-01", Some(("trip", 2)))),
+            ("third-b", post("", "2022-02-01", Some(("trip", 3)))),
+            ("third-a", post("", "2022-02-01", Some(("trip", 3)))),
+            ("alone", post("", "2022-01-01", None)),
+        ]);
+        let navigation = build_navigation(&posts);
+
+        let series = navigation["second"].series.as_ref().unwrap();
+        let parts: Vec<&str> = series.parts.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(parts, ["first", "second", "third-a", "third-b"]);
+        assert_eq!(series.name, "trip");
+        assert_eq!(series.position, 2);
+        assert_eq!(series.previous.as_ref().unwrap().name, "first");
+        assert_eq!(series.next.as_ref().unwrap().name, "third-a");
+        assert!(navigation["first"]
+            .series
+            .as_ref()
+            .unwrap()
+            .previous
+            .is_none());
+        assert!(navigation["third-b"]
+            .series
+            .as_ref()
+            .unwrap()
+            .next
+            .is_none());
+        assert!(navigation["alone"].series.is_none());
+    }
+
+    #[test]
+    fn test_same_day_posts_are_ordered_by_name() {
+        let posts = posts(vec![
+            ("b", post("", "2022-01-01", None)),
+            ("a", post("", "2022-01-01", None)),
+            ("c", post("", "2022-01-01", None)),
+        ]);
+        let navigation = build_navigation(&posts);
+
+        assert!(navigation["a"].previous.is_none());
+        assert_eq!(navigation["a"].next.as_ref().unwrap().name, "b");
+        assert_eq!(navigation["b"].previous.as_ref().unwrap().name, "a");
+        assert_eq!(navigation["b"].next.as_ref().unwrap().name, "c");
+        assert!(navigation["c"].next.is_none());
+    }
+
+    fn wikilinks(names: &[&str]) -> String {
+        names
+            .iter()
+            .map(|name| {
+                format!(
+                    "<a class=\"wikilink\" href=\"wikilink:{}\">{}</a>",
+                    name, name
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_backlinks_resolve_and_deduplicate() {
+        let mut posts = posts(vec![
+            ("one.post", post("", "2022-01-01", None)),
+            ("two.post", post("", "2022-01-02", None)),
+        ]);
+        posts.get_mut("one.post").unwrap().html =
+            wikilinks(&["two", "two.post", "about", "one", "missing"]);
+        posts.get_mut("two.post").unwrap().html = wikilinks(&["about.page"]);
+        let pages: HashMap<String, Page> = [(
+            String::from("about.page"),
+            Page::try_from(json!({
+                "metadata": { "author_name": "author", "published_date": "2022-01-01" },
+                "title": "About",
+                "content": wikilinks(&["one.post"]),
+            }))
+            .unwrap(),
+        )]
+        .into_iter()
+        .collect();
+        let page_urls = HashMap::from([(String::from("about.page"), String::from("about.html"))]);
+
+        let backlinks = build_backlinks(&posts, &pages, &HashMap::new(), &page_urls);
+        let names = |target: &str| -> Vec<String> {
+            backlinks[target].iter().map(|l| l.name.clone()).collect()
+        };
+
+        assert_eq!(names("two.post"), ["one.post"]);
+        assert_eq!(names("about.page"), ["one.post", "two.post"]);
+        assert_eq!(names("one.post"), ["about.page"]);
+        assert_eq!(backlinks["one.post"][0].url, "about.html");
+        assert_eq!(backlinks.len(), 3);
+    }
+
+    #[test]
+    fn test_content_names_match_with_or_without_extension() {
+        assert!(matches_content_name(
+            "notes/garden.post",
+            "notes/garden.post"
+        ));
+        assert!(matches_content_name("notes/garden.post", "notes/garden"));
+        assert!(matches_content_name("about", "about"));
+        assert!(!matches_content_name("notes/garden.post", "garden"));
+        assert!(!matches_content_name(
+            "notes/garden.post",
+            "notes/garden.page"
+        ));
+        assert!(!matches_content_name("v1.0/notes", "v1"));
+    }
+}
@@ -1,12 +1,31 @@
 mod blocks;
+mod frontmatter;
+mod gemtext;
+mod includes;
+mod schema;
+mod shortcodes;
+mod templated;
+mod wikilinks;
 
+use crate::compress::CompressionConfig;
+use crate::feeds::FeedConfig;
 use crate::files::{read_file_contents, Error as FilesError};
+use crate::images::{self, ImageConfig};
+pub(crate) use blocks::BlockSource;
 use blocks::Blocks;
+pub(crate) use frontmatter::parse_front_matter;
+use includes::Includes;
+use pulldown_cmark::escape::escape_html;
+use schema::FieldType;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json;
 use std::path::Path;
 use thiserror::Error;
 
+pub(crate) use shortcodes::{expand_shortcodes, Shortcode};
+pub(crate) use templated::render_templated_blocks;
+pub(crate) use wikilinks::{collect_wikilinks, resolve_gemtext_wikilinks, resolve_wikilinks};
+
 #[derive(Debug, Error)]
 pub(crate) enum Error {
     #[error("Couldn't load content")]
@@ -18,11 +37,51 @@ pub(crate) enum Error {
     #[error("Couldn't parse json")]
     JsonParseError { source: serde_json::Error },
 
+    #[error("Couldn't parse toml")]
+    TomlParseError { source: toml::de::Error },
+
+    #[error("Couldn't parse yaml")]
+    YamlParseError { source: serde_yaml::Error },
+
     #[error("Block header was malformed: '{reason}'")]
     MalformedBlockHeaderError { reason: String },
 
+    #[error("Block path '{path}' couldn't be assigned: {reason}")]
+    MalformedBlockPathError { path: String, reason: String },
+
+    #[error("Block at line {line} overwrites '{path}', which was set by the block at line {previous_line}; add '!merge' or '!replace' to its header if that's intended")]
+    BlockConflictError {
+        path: String,
+        line: usize,
+        previous_line: usize,
+    },
+
+    #[error("Couldn't read included file '{path}'")]
+    IncludeReadError { source: FilesError, path: String },
+
+    #[error("Included block '{block_path}' isn't in '{path}'")]
+    MissingIncludedBlockError { path: String, block_path: String },
+
+    #[error("Includes form a cycle: {chain}")]
+    IncludeCycleError { chain: String },
+
     #[error("Block content was malformed: '{reason}'")]
     MalformedBlockContentError { reason: String },
+
+    #[error("Content file doesn't start with a type declaration ('type::<name>')")]
+    MissingTypeDeclarationError,
+
+    #[error("Front matter was malformed: {reason}")]
+    MalformedFrontMatterError { reason: String },
+
+    #[error("Content type '{name}' isn't declared in config.json")]
+    UnknownContentTypeError { name: String },
+
+    #[error("'{content_type}' content doesn't match its fields:\n{violations}")]
+    SchemaViolationError {
+        content_type: String,
+        violations: String,
+    },
 }
 
 ///
@@ -33,6 +92,16 @@ pub(crate) struct SiteContext {
     pub site_title: String,
     pub language_code: String,
     pub sections: Vec<SiteSection>,
+    pub site_url: Option<String>,
+    pub images: Option<ImageConfig>,
+    pub feed: Option<FeedConfig>,
+    pub compression: Option<CompressionConfig>,
+    pub content_types: Option<Vec<SiteContentType>>,
+    /// The author new content is scaffolded with.
+    pub author_name: Option<String>,
+    /// Any other values (e.g. analytics settings), passed through to templates.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -40,12 +109,35 @@ pub(crate) struct SiteSection {
     pub name: String,
     pub site_path: String,
     pub index_content: String,
+    pub feed: Option<FeedConfig>,
 }
 
+/// A user-defined content type, declared with `type::<name>` in block files.
 #[derive(Deserialize, Serialize, Clone, Debug)]
-pub struct SiteContentType {
+pub(crate) struct SiteContentType {
     pub name: String,
     pub content_template: String,
+    pub directory: Option<String>,
+    /// The fields entries may have. When declared, any other field is
+    /// rejected.
+    pub fields: Option<Vec<ContentField>>,
+}
+impl SiteContentType {
+    /// The site-scoped directory entries of this type are rendered to.
+    pub(crate) fn directory(&self) -> String {
+        self.directory.clone().unwrap_or_else(|| self.name.clone())
+    }
+}
+
+/// A field of a content type. Objects (and arrays of objects) can declare
+/// their own nested `fields`.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub(crate) struct ContentField {
+    pub name: String,
+    pub required: Option<bool>,
+    #[serde(rename = "type")]
+    pub field_type: Option<FieldType>,
+    pub fields: Option<Vec<ContentField>>,
 }
 
 ///
@@ -65,6 +157,48 @@ impl TryFrom<JsonString> for serde_json::Value {
     }
 }
 
+#[derive(Serialize, Debug)]
+pub(crate) struct TomlString {
+    content: String,
+}
+impl TryFrom<TomlString> for serde_json::Value {
+    type Error = Error;
+
+    fn try_from(toml: TomlString) -> Result<Self, Self::Error> {
+        let toml: toml::Value = toml::from_str(toml.content.as_str())
+            .map_err(|e| Error::TomlParseError { source: e })?;
+        Ok(toml_to_json(toml))
+    }
+}
+
+/// TOML datetimes become strings, rather than serde's private datetime struct.
+pub(crate) fn toml_to_json(value: toml::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => Value::from(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(a) => Value::Array(a.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(t) => {
+            Value::Object(t.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect())
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct YamlString {
+    content: String,
+}
+impl TryFrom<YamlString> for serde_json::Value {
+    type Error = Error;
+
+    fn try_from(yaml: YamlString) -> Result<Self, Self::Error> {
+        serde_yaml::from_str(yaml.content.as_str()).map_err(|e| Error::YamlParseError { source: e })
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub(crate) struct MarkdownString {
     content: String,
@@ -85,6 +219,76 @@ impl From<SamString> for serde_json::Value {
     }
 }
 
+#[derive(Serialize, Debug)]
+pub(crate) struct DjotString {
+    content: String,
+}
+impl From<DjotString> for serde_json::Value {
+    fn from(djot: DjotString) -> Self {
+        serde_json::Value::String(djot.content)
+    }
+}
+
+/// Djot events that carry `html` through to the output as is.
+pub(crate) fn djot_raw_html<'s>(html: String) -> [jotdown::Event<'s>; 3] {
+    let format = "html";
+    [
+        jotdown::Event::Start(
+            jotdown::Container::RawInline { format },
+            jotdown::Attributes::new(),
+        ),
+        jotdown::Event::Str(html.into()),
+        jotdown::Event::End(jotdown::Container::RawInline { format }),
+    ]
+}
+
+/// Whether a djot container holds code or raw output, which is left alone.
+pub(crate) fn is_djot_code(container: &jotdown::Container) -> bool {
+    matches!(
+        container,
+        jotdown::Container::Verbatim
+            | jotdown::Container::CodeBlock { .. }
+            | jotdown::Container::RawBlock { .. }
+            | jotdown::Container::RawInline { .. }
+            | jotdown::Container::Math { .. }
+    )
+}
+
+/// The text a djot event stands for, with smart punctuation as it was typed,
+/// so text split across events can be searched as written.
+pub(crate) fn djot_text<'a>(event: &'a jotdown::Event) -> Option<&'a str> {
+    match event {
+        jotdown::Event::Str(text) => Some(&**text),
+        jotdown::Event::LeftSingleQuote | jotdown::Event::RightSingleQuote => Some("'"),
+        jotdown::Event::LeftDoubleQuote | jotdown::Event::RightDoubleQuote => Some("\""),
+        jotdown::Event::Ellipsis => Some("..."),
+        jotdown::Event::EnDash => Some("--"),
+        jotdown::Event::EmDash => Some("---"),
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct TextString {
+    content: String,
+}
+impl From<TextString> for serde_json::Value {
+    fn from(text: TextString) -> Self {
+        serde_json::Value::String(text.content)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub(crate) struct CodeString {
+    content: String,
+    language: Option<String>,
+}
+impl From<CodeString> for serde_json::Value {
+    fn from(code: CodeString) -> Self {
+        serde_json::Value::String(code.content)
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub(crate) struct HtmlString {
     content: String,
@@ -93,7 +297,10 @@ impl From<MarkdownString> for HtmlString {
     fn from(markdown: MarkdownString) -> Self {
         let mut html = String::new();
         let parser = pulldown_cmark::Parser::new(&markdown.content);
-        pulldown_cmark::html::push_html(&mut html, parser);
+        let events = images::expand_markdown_events(parser);
+        let events = shortcodes::expand_markdown_events(events);
+        let events = wikilinks::expand_markdown_events(events);
+        pulldown_cmark::html::push_html(&mut html, events);
 
         HtmlString { content: html }
     }
@@ -101,21 +308,100 @@ impl From<MarkdownString> for HtmlString {
 impl From<SamString> for HtmlString {
     fn from(sam: SamString) -> Self {
         let html = match sam.content.parse::<sam_rs::Element>() {
-            Ok(element) => element.to_xml(0, false),
+            Ok(element) => wikilinks::expand_html(&element.to_xml(0, false)),
             Err(err) => panic!("{}", err),
         };
         HtmlString { content: html }
     }
 }
+impl From<DjotString> for HtmlString {
+    fn from(djot: DjotString) -> Self {
+        let events = jotdown::Parser::new(&djot.content);
+        let events = images::expand_djot_events(events);
+        let events = shortcodes::expand_djot_events(events);
+        let events = wikilinks::expand_djot_events(events);
+        let html = jotdown::html::render_to_string(events);
+
+        HtmlString { content: html }
+    }
+}
+impl From<TextString> for HtmlString {
+    /// Blank lines separate paragraphs; single newlines become line breaks.
+    /// Leading whitespace is kept as non-breaking spaces, a tab counting as
+    /// four.
+    fn from(text: TextString) -> Self {
+        let mut html = String::new();
+        let paragraphs = text
+            .content
+            .split("\n\n")
+            .map(|paragraph| paragraph.trim_matches('\n'))
+            .filter(|paragraph| !paragraph.trim().is_empty());
+        for paragraph in paragraphs {
+            html.push_str("<p>");
+            for (index, line) in paragraph.lines().enumerate() {
+                if index > 0 {
+                    html.push_str("<br />\n");
+                }
+                let content = line.trim_start_matches([' ', '\t']);
+                for c in line[..line.len() - content.len()].chars() {
+                    match c {
+                        '\t' => html.push_str("&nbsp;&nbsp;&nbsp;&nbsp;"),
+                        _ => html.push_str("&nbsp;"),
+                    }
+                }
+                escape_html(&mut html, content).unwrap();
+            }
+            html.push_str("</p>\n");
+        }
+        HtmlString { content: html }
+    }
+}
+impl From<CodeString> for HtmlString {
+    fn from(code: CodeString) -> Self {
+        let mut html = String::new();
+        match &code.language {
+            Some(language) => {
+                html.push_str("<pre><code class=\"language-");
+                escape_html(&mut html, language).unwrap();
+                html.push_str("\">");
+            }
+            None => html.push_str("<pre><code>"),
+        }
+        escape_html(&mut html, &code.content).unwrap();
+        html.push_str("</code></pre>\n");
+        HtmlString { content: html }
+    }
+}
 impl From<HtmlString> for serde_json::Value {
     fn from(html: HtmlString) -> Self {
         serde_json::Value::String(html.content)
     }
 }
 
+#[derive(Serialize, Debug)]
+pub(crate) struct GemtextString {
+    content: String,
+}
+impl From<GemtextString> for serde_json::Value {
+    fn from(gemtext: GemtextString) -> Self {
+        serde_json::Value::String(gemtext.content)
+    }
+}
+
 pub(crate) enum Content {
     Post(Post),
     Page(Page),
+    Entry(Entry),
+}
+impl Content {
+    /// The sources of the content's blocks.
+    pub(crate) fn sources(&self) -> &[BlockSource] {
+        match self {
+            Content::Post(post) => &post.sources,
+            Content::Page(page) => &page.sources,
+            Content::Entry(entry) => &entry.sources,
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -131,6 +417,11 @@ pub(crate) struct Post {
     pub title: String,
     pub slug: String,
     pub html: String,
+    pub gemtext: Option<String>,
+    #[serde(skip)]
+    pub blocks: serde_json::Value,
+    #[serde(skip)]
+    pub sources: Vec<BlockSource>,
 }
 impl TryFrom<serde_json::Value> for Post {
     type Error = Error;
@@ -148,6 +439,9 @@ impl From<PostOption> for Post {
             title: option.title,
             slug: option.slug,
             html: option.content,
+            gemtext: None,
+            blocks: serde_json::Value::Null,
+            sources: Vec::new(),
         }
     }
 }
@@ -163,6 +457,11 @@ pub(crate) struct Page {
     pub metadata: Metadata,
     pub title: String,
     pub html: String,
+    pub gemtext: Option<String>,
+    #[serde(skip)]
+    pub blocks: serde_json::Value,
+    #[serde(skip)]
+    pub sources: Vec<BlockSource>,
 }
 impl TryFrom<serde_json::Value> for Page {
     type Error = Error;
@@ -179,10 +478,111 @@ impl From<PageOption> for Page {
             metadata: option.metadata.into(),
             title: option.title,
             html: option.content,
+            gemtext: None,
+            blocks: serde_json::Value::Null,
+            sources: Vec::new(),
         }
     }
 }
 
+/// Content of a user-defined type: its declared fields, plus a slug.
+#[derive(Serialize)]
+pub(crate) struct Entry {
+    pub content_type: String,
+    pub slug: String,
+    pub url: Option<String>,
+    #[serde(flatten)]
+    pub fields: serde_json::Map<String, serde_json::Value>,
+    pub gemtext: Option<String>,
+    #[serde(skip)]
+    pub blocks: serde_json::Value,
+    #[serde(skip)]
+    pub sources: Vec<BlockSource>,
+    /// Whether the entry is built outside of drafts, taken from a `build`
+    /// field.
+    #[serde(skip)]
+    pub build: bool,
+}
+impl Entry {
+    /// The slug is taken from a `slug` field, falling back to `default_slug`.
+    fn try_from_json(
+        json: serde_json::Value,
+        content_type: &SiteContentType,
+        default_slug: &str,
+    ) -> Result<Self, Error> {
+        let mut fields = match json {
+            serde_json::Value::Object(fields) => fields,
+            serde_json::Value::Null => serde_json::Map::new(),
+            _ => {
+                return Err(Error::MalformedBlockContentError {
+                    reason: format!("'{}' content must be an object", content_type.name),
+                })
+            }
+        };
+        let slug = match fields.remove("slug") {
+            Some(serde_json::Value::String(slug)) => String::from(slug.trim()),
+            _ => String::from(default_slug),
+        };
+        let build = match fields.remove("build") {
+            Some(serde_json::Value::Bool(build)) => build,
+            _ => true,
+        };
+
+        Ok(Entry {
+            content_type: content_type.name.clone(),
+            slug,
+            url: None,
+            fields,
+            gemtext: None,
+            blocks: serde_json::Value::Null,
+            sources: Vec::new(),
+            build,
+        })
+    }
+
+    /// The `title` field, falling back to the slug.
+    pub(crate) fn title(&self) -> &str {
+        self.fields
+            .get("title")
+            .and_then(serde_json::Value::as_str)
+            .unwrap_or(&self.slug)
+    }
+
+    /// Every string field joined together, which holds the html of the
+    /// entry's markdown fields.
+    pub(crate) fn html(&self) -> String {
+        fn push_strings(value: &serde_json::Value, html: &mut String) {
+            match value {
+                serde_json::Value::String(s) => {
+                    html.push_str(s);
+                    html.push('\n');
+                }
+                serde_json::Value::Array(values) => {
+                    values.iter().for_each(|value| push_strings(value, html))
+                }
+                serde_json::Value::Object(fields) => {
+                    fields.values().for_each(|value| push_strings(value, html))
+                }
+                _ => {}
+            }
+        }
+        let mut html = String::new();
+        self.fields
+            .values()
+            .for_each(|value| push_strings(value, &mut html));
+        html
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub(crate) struct Attachment {
+    pub url: String,
+    pub mime_type: String,
+    pub title: Option<String>,
+    pub size_in_bytes: Option<u64>,
+    pub duration_in_seconds: Option<u64>,
+}
+
 #[derive(Deserialize)]
 struct MetadataOption {
     content_name: Option<String>,
@@ -192,6 +592,10 @@ struct MetadataOption {
     updated_date: Option<String>,
     version: Option<u32>,
     build: Option<bool>,
+    series: Option<String>,
+    series_order: Option<u32>,
+    tags: Option<Vec<String>>,
+    attachments: Option<Vec<Attachment>>,
 }
 #[derive(Serialize)]
 pub(crate) struct Metadata {
@@ -202,6 +606,10 @@ pub(crate) struct Metadata {
     pub updated_date: String,
     pub version: u32,
     pub build: bool,
+    pub series: Option<String>,
+    pub series_order: Option<u32>,
+    pub tags: Vec<String>,
+    pub attachments: Vec<Attachment>,
 }
 impl From<MetadataOption> for Metadata {
     fn from(option: MetadataOption) -> Self {
@@ -213,34 +621,143 @@ impl From<MetadataOption> for Metadata {
             published_date: option.published_date,
             version: option.version.unwrap_or(1),
             build: option.build.unwrap_or(true),
+            series: option.series,
+            series_order: option.series_order,
+            tags: option.tags.unwrap_or_default(),
+            attachments: option.attachments.unwrap_or_default(),
         }
     }
 }
 
-pub(crate) fn parse_blocks_file(path: impl AsRef<std::path::Path>) -> Result<Content, Error> {
-    let file_contents =
-        read_file_contents(&path).map_err(|e| Error::ContentLoadError { source: e })?;
-    let (type_declaration, file_contents) = match file_contents.split_once("\n") {
-        Some(strings) => strings,
-        None => {
-            return Err(Error::MalformedBlockHeaderError {
-                reason: String::from("no newline in file"),
-            })
+/// Splits the contents of a block file into its `type::` declaration and its
+/// blocks, along with the number of lines before the blocks. A byte order
+/// mark and blank lines before the declaration are skipped.
+fn split_type_declaration(contents: &str) -> Option<(&str, &str, usize)> {
+    let mut rest = contents.strip_prefix('\u{feff}').unwrap_or(contents);
+    let mut lines = 0;
+    loop {
+        let (line, remaining) = rest.split_once('\n').unwrap_or((rest, ""));
+        let line = line.trim_end();
+        lines += 1;
+        if line.is_empty() && !remaining.is_empty() {
+            rest = remaining;
+            continue;
         }
+        return match line.starts_with("type::") {
+            true => Some((line, remaining, lines)),
+            false => None,
+        };
+    }
+}
+
+/// Formats the contents of a block file canonically, or returns `None` if
+/// it isn't a block file. Includes are left as they are.
+pub(crate) fn format_blocks(contents: &str) -> Result<Option<String>, Error> {
+    let (type_declaration, blocks) = match split_type_declaration(contents) {
+        Some((type_declaration, blocks, _)) => (type_declaration, blocks),
+        None => return Ok(None),
     };
+    let blocks = Blocks::parse(blocks, &mut Includes::skipped())?;
+    Ok(Some(format!(
+        "{}\n{}",
+        type_declaration.trim_end(),
+        blocks.to_text()
+    )))
+}
+
+pub(crate) fn parse_blocks_file(
+    path: impl AsRef<std::path::Path>,
+    content_types: &[SiteContentType],
+) -> Result<Content, Error> {
+    let file_contents =
+        read_file_contents(&path).map_err(|e| Error::ContentLoadError { source: e })?;
+    let (type_declaration, file_contents, declaration_lines) =
+        split_type_declaration(&file_contents).ok_or(Error::MissingTypeDeclarationError)?;
 
     // println!("\nparsing blocks\n==============\n");
-    let blocks: Blocks = file_contents.parse()?;
+    let mut blocks = Blocks::parse(file_contents, &mut Includes::new(&path))?;
+    // block lines are counted from after the type declaration
+    blocks.offset_lines(declaration_lines);
+    let gemtext = blocks.gemtext();
+    let sources = blocks.sources();
     // println!("\nblocks -> json\n==============\n");
     let json: serde_json::Value = blocks.try_into()?;
 
     // println!("\njson -> content\n===============\n");
+    let gemtext_content = |block_type: &str| {
+        gemtext[block_type]["content"]
+            .as_str()
+            .map(|gemtext| gemtext.to_owned())
+    };
+    let validate = |name: &str, fields: &[ContentField], strict: bool| {
+        let violations = schema::validate(&json[name], fields, name, strict, &sources);
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(Error::SchemaViolationError {
+                content_type: String::from(name),
+                violations: violations
+                    .iter()
+                    .map(|violation| format!("  {}", violation))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            }),
+        }
+    };
     match type_declaration {
-        "type::post" => Ok(Content::Post(json["post"].clone().try_into()?)),
-        "type::page" => Ok(Content::Page(json["page"].clone().try_into()?)),
-        _ => Err(Error::MalformedBlockHeaderError {
-            reason: format!("invalid type header"),
-        }),
+        "type::post" => {
+            validate("post", &schema::builtin_fields("post"), false)?;
+            let mut post: Post = json["post"].clone().try_into()?;
+            post.gemtext = gemtext_content("post");
+            post.blocks = json["post"].clone();
+            post.sources = sources;
+            Ok(Content::Post(post))
+        }
+        "type::page" => {
+            validate("page", &schema::builtin_fields("page"), false)?;
+            let mut page: Page = json["page"].clone().try_into()?;
+            page.gemtext = gemtext_content("page");
+            page.blocks = json["page"].clone();
+            page.sources = sources;
+            Ok(Content::Page(page))
+        }
+        _ => {
+            let name = type_declaration.strip_prefix("type::").ok_or_else(|| {
+                Error::MalformedBlockHeaderError {
+                    reason: format!("invalid type header"),
+                }
+            })?;
+            let content_type = content_types
+                .iter()
+                .find(|content_type| content_type.name == name)
+                .ok_or_else(|| Error::UnknownContentTypeError {
+                    name: String::from(name),
+                })?;
+            let mut fields = content_type.fields.clone().unwrap_or_default();
+            fields.push(ContentField {
+                name: String::from("slug"),
+                required: Some(false),
+                field_type: Some(FieldType::String),
+                fields: None,
+            });
+            fields.push(ContentField {
+                name: String::from("build"),
+                required: Some(false),
+                field_type: Some(FieldType::Boolean),
+                fields: None,
+            });
+            // a type without declared fields takes any field
+            validate(name, &fields, content_type.fields.is_some())?;
+            let default_slug = path
+                .as_ref()
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let mut entry = Entry::try_from_json(json[name].clone(), content_type, &default_slug)?;
+            entry.gemtext = gemtext_content(name);
+            entry.blocks = json[name].clone();
+            entry.sources = sources;
+            Ok(Content::Entry(entry))
+        }
     }
 }
 
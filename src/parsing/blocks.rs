@@ -1,6 +1,94 @@
-use super::{Error, HtmlString, JsonString, MarkdownString, SamString};
+use super::includes::Includes;
+use super::templated::{self, TemplatedSource};
+use super::{
+    CodeString, DjotString, Error, GemtextString, HtmlString, JsonString, MarkdownString,
+    SamString, TextString, TomlString, YamlString,
+};
+use serde::Serialize;
+
+/// A block as it was written, before any transformation.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct BlockSource {
+    pub header: String,
+    pub path: String,
+    pub line: usize,
+    pub content: String,
+    /// The files the content was included from, if it wasn't written inline.
+    /// These are absolute paths on the build machine, so they're kept out of
+    /// the api.
+    #[serde(skip)]
+    pub includes: Vec<String>,
+}
 
 pub(crate) struct Blocks(Vec<Block>);
+impl Blocks {
+    /// Shifts every block's line number, for blocks parsed from the middle of a file.
+    pub(crate) fn offset_lines(&mut self, offset: usize) {
+        for block in &mut self.0 {
+            block.header.line_number += offset;
+        }
+    }
+
+    /// Writes the blocks back out canonically: normalized headers, one blank
+    /// line between blocks, the shortest fence the content allows, and
+    /// pretty-printed json. Any other content is kept byte for byte.
+    pub(crate) fn to_text(&self) -> String {
+        let blocks: Vec<String> = self
+            .0
+            .iter()
+            .map(|block| {
+                if block.header.include.is_some() {
+                    return format!("{}\n", block.header);
+                }
+                let is_json = block.header.encoding.source.is_none()
+                    && matches!(block.header.encoding.encoding, BlockEncodings::Json);
+                let mut content = match is_json {
+                    true => pretty_json(&block.raw).unwrap_or_else(|| block.raw.clone()),
+                    false => block.raw.clone(),
+                };
+                if !content.is_empty() && !content.ends_with('\n') {
+                    content.push('\n');
+                }
+                let fence = fence_for(&content);
+                format!("{}\n{}\n{}{}\n", block.header, fence, content, fence)
+            })
+            .collect();
+        blocks.join("\n")
+    }
+
+    /// The source of every block, in file order.
+    pub(crate) fn sources(&self) -> Vec<BlockSource> {
+        self.0
+            .iter()
+            .map(|block| BlockSource {
+                header: block.header.line.clone(),
+                path: block.header.path.to_string(),
+                line: block.header.line_number,
+                content: block.source.clone(),
+                includes: block.includes.clone(),
+            })
+            .collect()
+    }
+
+    /// The gemtext rendition of every block that has one, at the same paths
+    /// the blocks occupy in the json tree.
+    pub(crate) fn gemtext(&self) -> serde_json::Value {
+        let mut json = serde_json::json!({});
+        for block in &self.0 {
+            let (mut path, last) = block.header.path.components();
+            path.extend(last);
+            // paths that can't be assigned are reported when building the json
+            if let Ok((slot, _)) = ensure_path(&mut json, &path) {
+                // a later block without a rendition still replaces an earlier one
+                *slot = match &block.gemtext {
+                    Some(gemtext) => serde_json::Value::String(gemtext.content.clone()),
+                    None => serde_json::Value::Null,
+                };
+            }
+        }
+        json
+    }
+}
 impl TryFrom<Blocks> for serde_json::Value {
     type Error = Error;
 
@@ -9,103 +97,304 @@ impl TryFrom<Blocks> for serde_json::Value {
 
         let blocks = blocks.0;
         let mut json = json!({});
+        // the resolved path and line of every block assigned so far
+        let mut assignments: Vec<(String, usize)> = Vec::new();
 
         for block in blocks {
-            // prep block path data
-            let block_type_key = String::from(block.header.path.block_type.as_str());
-            let mut child_path = block.header.path.path.clone();
-
-            // create a path
-            let mut path = Vec::new();
-            path.push(block_type_key);
-            path.append(&mut child_path);
-            let last = path.pop();
-
-            // unwrapped because we just created this above, known value
-            let mut obj = json.as_object_mut().unwrap();
-
-            // ensure path
-            for component in &path {
-                if None == obj.get_mut(component) {
-                    obj.insert(component.clone(), json!({}));
+            // find the value at the block's path, creating it if needed
+            let (mut path, last) = block.header.path.components();
+            path.extend(last);
+            let (slot, resolved) =
+                ensure_path(&mut json, &path).map_err(|reason| Error::MalformedBlockPathError {
+                    path: block.header.path.to_string(),
+                    reason,
+                })?;
+
+            // create Value from content block
+            let line = block.header.line_number;
+            let modifiers = &block.header.modifiers;
+            let json: Value = block.content.try_into()?;
+            if modifiers.contains(&BlockModifier::Replace) {
+                *slot = json;
+            } else if modifiers.contains(&BlockModifier::Merge) {
+                merge(slot, json);
+            } else if let Some(conflict) = overlap(slot, &json) {
+                let conflict = format!("{}{}", resolved, conflict);
+                let previous_line = assignments
+                    .iter()
+                    .rev()
+                    .find(|(path, _)| paths_overlap(path, &conflict))
+                    .map(|(_, line)| *line)
+                    .expect("anything a block overlaps was assigned by an earlier block");
+                return Err(Error::BlockConflictError {
+                    path: conflict,
+                    line,
+                    previous_line,
+                });
+            } else {
+                // objects fill in each other's keys; anything else fills an empty slot
+                match (slot, json) {
+                    (Value::Object(parent_map), Value::Object(mut map)) => {
+                        parent_map.append(&mut map)
+                    }
+                    (slot, json) => *slot = json,
                 }
-                obj = obj.get_mut(component).unwrap().as_object_mut().unwrap();
             }
+            assignments.push((resolved, line));
+        }
+
+        Ok(json)
+    }
+}
 
-            if let Some(last) = last {
-                // create Value from content block
-                let json: Value = block.content.try_into()?;
-                // if it's a map, append to existing object or insert it if there isn't one
-                if let Value::Object(mut map) = json {
-                    if let Some(Value::Object(parent_map)) = obj.get_mut(&last) {
-                        parent_map.append(&mut map);
-                    } else {
-                        obj.insert(last, Value::Object(map));
+/// Re-indents json without reordering its keys, as long as it's valid.
+fn pretty_json(json: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(json).ok()?;
+
+    let mut pretty = String::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut chars = json.trim().chars().peekable();
+    let newline = |pretty: &mut String, depth: usize| {
+        pretty.push('\n');
+        pretty.push_str(&"  ".repeat(depth));
+    };
+    while let Some(c) = chars.next() {
+        if in_string {
+            pretty.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => {
+                in_string = true;
+                pretty.push(c);
+            }
+            '{' | '[' => {
+                pretty.push(c);
+                // keep empty objects and arrays on one line
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                match chars.peek() {
+                    Some('}' | ']') => pretty.push(chars.next().unwrap()),
+                    _ => {
+                        depth += 1;
+                        newline(&mut pretty, depth);
                     }
-                // otherwise just insert
-                } else {
-                    obj.insert(last, json);
                 }
             }
+            '}' | ']' => {
+                depth -= 1;
+                newline(&mut pretty, depth);
+                pretty.push(c);
+            }
+            ',' => {
+                pretty.push(c);
+                newline(&mut pretty, depth);
+            }
+            ':' => pretty.push_str(": "),
+            _ if c.is_whitespace() => {}
+            _ => pretty.push(c),
         }
+    }
+    pretty.push('\n');
+    Some(pretty)
+}
 
-        Ok(json)
+/// Where assigning `value` onto `existing` would overwrite something, as a
+/// path relative to `existing`. Objects only conflict on the keys they share.
+fn overlap(existing: &serde_json::Value, value: &serde_json::Value) -> Option<String> {
+    use serde_json::Value;
+    match (existing, value) {
+        (Value::Null, _) => None,
+        (Value::Object(existing), Value::Object(value)) => value
+            .keys()
+            .find(|key| existing.get(*key).is_some_and(|v| !v.is_null()))
+            .map(|key| format!(".{}", key)),
+        _ => Some(String::new()),
+    }
+}
+
+/// Whether one resolved path is the same as, or nested within, the other.
+fn paths_overlap(a: &str, b: &str) -> bool {
+    let nested = |outer: &str, inner: &str| {
+        inner
+            .strip_prefix(outer)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+    };
+    nested(a, b) || nested(b, a)
+}
+
+/// Merges objects key by key; anything else in `value` replaces `base`.
+fn merge(base: &mut serde_json::Value, value: serde_json::Value) {
+    use serde_json::Value;
+    match (base, value) {
+        (Value::Object(base), Value::Object(value)) => {
+            for (key, value) in value {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+/// Walks `path` down from `json`, creating objects and arrays as they're
+/// needed, and returns the value it ends at (null if it didn't exist yet)
+/// along with its path, with every `[]` resolved to an index.
+fn ensure_path<'a>(
+    json: &'a mut serde_json::Value,
+    path: &[PathSegment],
+) -> Result<(&'a mut serde_json::Value, String), String> {
+    use serde_json::Value;
+
+    let mut value = json;
+    let mut resolved = String::new();
+    for segment in path {
+        value = match segment {
+            PathSegment::Key(key) => {
+                if value.is_null() {
+                    *value = serde_json::json!({});
+                }
+                value
+                    .as_object_mut()
+                    .ok_or_else(|| format!("can't set key '{}' on a non-object", key))?
+                    .entry(key.clone())
+                    .or_insert(Value::Null)
+            }
+            PathSegment::Index(_) | PathSegment::Append => {
+                if value.is_null() {
+                    *value = serde_json::json!([]);
+                }
+                let array = value
+                    .as_array_mut()
+                    .ok_or_else(|| String::from("can't index into a non-array"))?;
+                let index = match segment {
+                    PathSegment::Index(index) => *index,
+                    _ => array.len(),
+                };
+                if index > array.len() {
+                    return Err(format!(
+                        "index {} is past the end of an array of {}",
+                        index,
+                        array.len()
+                    ));
+                }
+                if index == array.len() {
+                    array.push(Value::Null);
+                }
+                resolved.push_str(&PathSegment::Index(index).to_string());
+                &mut array[index]
+            }
+        };
+        if let PathSegment::Key(_) = segment {
+            resolved.push_str(&segment.to_string());
+        }
     }
+    // keys are written with a leading '.', which the root doesn't need
+    let resolved = resolved.strip_prefix('.').unwrap_or(&resolved).to_string();
+    Ok((value, resolved))
 }
 impl std::str::FromStr for Blocks {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Blocks::parse(s, &mut Includes::default())
+    }
+}
+impl Blocks {
+    /// Parses blocks, reading any included content through `includes`.
+    pub(crate) fn parse(s: &str, includes: &mut Includes) -> Result<Blocks, Error> {
         enum State {
             ParseHeader,
-            WaitForContent { block_header: BlockHeader },
-            BufferContent { block_header: BlockHeader },
+            WaitForContent {
+                block_header: BlockHeader,
+            },
+            BufferContent {
+                block_header: BlockHeader,
+                fence: usize,
+            },
         }
         let mut state = State::ParseHeader;
         let mut blocks: Vec<Block> = Vec::new();
         let mut buffer = String::new();
-        let mut lines = s.lines();
-        while let Some(line) = lines.next() {
+        // content exactly as written, line endings and all, for formatting
+        let mut raw_buffer = String::new();
+        let mut lines = s.split_inclusive('\n').enumerate();
+        while let Some((index, raw_line)) = lines.next() {
+            let line = raw_line.strip_suffix('\n').unwrap_or(raw_line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
             state = match state {
                 State::ParseHeader => match line {
                     _ if line.is_empty() => state,
                     _ => {
-                        let block_header = line
+                        let mut block_header: BlockHeader = line
                             .parse()
                             .map_err(|e| Error::MalformedBlockHeaderError { reason: e })?;
-                        State::WaitForContent { block_header }
+                        block_header.line_number = index + 1;
+                        // included content takes the place of a content block
+                        match block_header.include.clone() {
+                            Some(target) => {
+                                let included = includes.resolve(&target)?;
+                                let block =
+                                    Block::new(block_header, included.content, included.files);
+                                blocks.push(block);
+                                State::ParseHeader
+                            }
+                            None => State::WaitForContent { block_header },
+                        }
                     }
                 },
                 State::WaitForContent { block_header } => match line {
                     _ if line.is_empty() => State::WaitForContent { block_header },
-                    "+++" => State::BufferContent { block_header },
+                    // like a markdown code fence, a longer marker lets content contain '+++'
+                    _ if is_fence(line) => State::BufferContent {
+                        block_header,
+                        fence: line.len(),
+                    },
                     _ => {
                         return Err(Error::MalformedBlockContentError {
                             reason: format!(
-                                "Expected content start marker ('+++') or blank line, found '{}'",
+                                "Expected content start marker ('+++' or longer) or blank line, found '{}'",
                                 line
                             ),
                         })
                     }
                 },
-                State::BufferContent { block_header } => match line {
-                    "+++" => {
-                        let block = Block::new(block_header, buffer.clone());
+                State::BufferContent {
+                    block_header,
+                    fence,
+                } => match line {
+                    _ if is_fence(line) && line.len() == fence => {
+                        let mut block = Block::new(block_header, buffer.clone(), Vec::new());
+                        block.raw = raw_buffer.clone();
                         blocks.push(block);
                         buffer.clear();
+                        raw_buffer.clear();
                         State::ParseHeader
                     }
                     _ => {
                         buffer.push_str(&format!("{}\n", line));
-                        State::BufferContent { block_header }
+                        raw_buffer.push_str(raw_line);
+                        State::BufferContent {
+                            block_header,
+                            fence,
+                        }
                     }
                 },
             };
         }
 
         // implicitly close an open content block at EOF
-        if let State::BufferContent { block_header } = state {
-            let block = Block::new(block_header, buffer);
+        if let State::BufferContent { block_header, .. } = state {
+            let mut block = Block::new(block_header, buffer, Vec::new());
+            block.raw = raw_buffer;
             blocks.push(block);
         }
 
@@ -113,14 +402,76 @@ impl std::str::FromStr for Blocks {
     }
 }
 
+/// Whether `line` opens or closes block content: three or more '+'.
+fn is_fence(line: &str) -> bool {
+    line.len() >= 3 && line.chars().all(|c| c == '+')
+}
+
+/// The shortest fence that `content` can't close early.
+pub(crate) fn fence_for(content: &str) -> String {
+    let longest = content
+        .lines()
+        .filter(|line| is_fence(line))
+        .map(str::len)
+        .max()
+        .unwrap_or(2);
+    "+".repeat(longest + 1)
+}
+
 struct Block {
     header: BlockHeader,
+    source: String,
+    /// the content as written, when it was written inline
+    raw: String,
+    includes: Vec<String>,
     content: BlockContent,
+    gemtext: Option<GemtextString>,
 }
 impl Block {
-    fn new(header: BlockHeader, content: String) -> Block {
+    fn new(header: BlockHeader, content: String, includes: Vec<String>) -> Block {
+        let templated = header.modifiers.contains(&BlockModifier::Template);
+        let gemtext = match (&header.encoding.source, &header.encoding.encoding) {
+            _ if templated => None,
+            (Some(BlockEncodings::Markdown), _) | (None, BlockEncodings::Markdown) => Some(
+                (MarkdownString {
+                    content: content.clone(),
+                })
+                .into(),
+            ),
+            (Some(BlockEncodings::Djot), _) | (None, BlockEncodings::Djot) => Some(
+                (DjotString {
+                    content: content.clone(),
+                })
+                .into(),
+            ),
+            (Some(BlockEncodings::Text), _) | (None, BlockEncodings::Text) => Some(
+                (TextString {
+                    content: content.clone(),
+                })
+                .into(),
+            ),
+            (Some(BlockEncodings::Code(language)), _) | (None, BlockEncodings::Code(language)) => {
+                Some(
+                    (CodeString {
+                        content: content.clone(),
+                        language: language.clone(),
+                    })
+                    .into(),
+                )
+            }
+            _ => None,
+        };
+        let source = content.clone();
+        let content = match templated {
+            true => BlockContent::templated(&header.encoding, content),
+            false => BlockContent::transform(&header.encoding, content),
+        };
         Block {
-            content: BlockContent::transform(&header.encoding, content),
+            source,
+            raw: String::new(),
+            includes,
+            content,
+            gemtext,
             header,
         }
     }
@@ -128,22 +479,121 @@ impl Block {
 
 #[derive(Debug)]
 struct BlockHeader {
+    line: String,
+    line_number: usize,
     path: BlockPath,
     encoding: BlockEncoding,
+    modifiers: Vec<BlockModifier>,
+    /// what to read the content from, when it isn't written inline
+    include: Option<String>,
 }
 impl std::str::FromStr for BlockHeader {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.split(":").collect::<Vec<_>>()[..] {
-            [block_path_str, encoding_str] => Ok(BlockHeader {
+        let mut tokens = s.split_whitespace();
+        let declaration = tokens.next().unwrap_or("");
+        let mut modifiers = Vec::new();
+        let mut include = None;
+        while let Some(token) = tokens.next() {
+            match token {
+                "<" => match tokens.next() {
+                    Some(target) if include.is_none() => include = Some(String::from(target)),
+                    Some(_) => return Err(format!("Only one include is allowed: {}", s)),
+                    None => return Err(format!("Expected a file to include after '<': {}", s)),
+                },
+                _ => modifiers.push(token.parse()?),
+            }
+        }
+
+        let header = match declaration.split_once(':') {
+            Some((block_path_str, encoding_str)) => BlockHeader {
+                line: String::from(s),
+                line_number: 0,
                 path: block_path_str.parse()?,
                 encoding: encoding_str.parse()?,
-            }),
-            _ => Err(format!(
-                "Expected header format 'type:encoding', received {}",
+                modifiers,
+                include,
+            },
+            None => {
+                return Err(format!(
+                    "Expected header format 'type:encoding [!modifier]', received {}",
+                    s
+                ))
+            }
+        };
+
+        if header.modifiers.contains(&BlockModifier::Template)
+            && matches!(
+                header.encoding.encoding,
+                BlockEncodings::Json | BlockEncodings::Toml | BlockEncodings::Yaml
+            )
+        {
+            return Err(format!(
+                "'!template' can't be applied to a data block: {}",
+                s
+            ));
+        }
+        if header.modifiers.contains(&BlockModifier::Template)
+            && matches!(header.encoding.source, Some(BlockEncodings::Code(_)))
+        {
+            return Err(format!(
+                "'!template' can't be applied to a code block: {}",
                 s
-            )),
+            ));
+        }
+
+        if header.modifiers.contains(&BlockModifier::Merge)
+            && header.modifiers.contains(&BlockModifier::Replace)
+        {
+            return Err(format!(
+                "'!merge' and '!replace' can't be applied to the same block: {}",
+                s
+            ));
+        }
+
+        Ok(header)
+    }
+}
+
+impl std::fmt::Display for BlockHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.path, self.encoding)?;
+        for modifier in &self.modifiers {
+            write!(f, " {}", modifier)?;
+        }
+        if let Some(include) = &self.include {
+            write!(f, " < {}", include)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum BlockModifier {
+    Template,
+    /// merge objects into whatever is already at the path, key by key
+    Merge,
+    /// replace whatever is already at the path
+    Replace,
+}
+impl std::str::FromStr for BlockModifier {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "!template" => Ok(BlockModifier::Template),
+            "!merge" => Ok(BlockModifier::Merge),
+            "!replace" => Ok(BlockModifier::Replace),
+            _ => Err(format!("'{}' is not a valid block modifier", s)),
+        }
+    }
+}
+impl std::fmt::Display for BlockModifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockModifier::Template => write!(f, "!template"),
+            BlockModifier::Merge => write!(f, "!merge"),
+            BlockModifier::Replace => write!(f, "!replace"),
         }
     }
 }
@@ -169,39 +619,83 @@ impl std::str::FromStr for BlockEncoding {
         }
     }
 }
+impl std::fmt::Display for BlockEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{}->{}", source, self.encoding),
+            None => write!(f, "{}", self.encoding),
+        }
+    }
+}
 
 #[derive(Debug)]
 enum BlockEncodings {
     Json,
+    Toml,
+    Yaml,
     Markdown,
     Html,
     Sam,
+    Djot,
+    Text,
+    /// Source code, optionally in a language: `code:rust`.
+    Code(Option<String>),
 }
 impl std::str::FromStr for BlockEncodings {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(language) = s.strip_prefix("code:") {
+            if language.is_empty() {
+                return Err(format!("Expected a language after 'code:', received {}", s));
+            }
+            return Ok(BlockEncodings::Code(Some(String::from(language))));
+        }
         match s {
             "json" => Ok(BlockEncodings::Json),
+            "toml" => Ok(BlockEncodings::Toml),
+            "yaml" => Ok(BlockEncodings::Yaml),
             "markdown" => Ok(BlockEncodings::Markdown),
             "html" => Ok(BlockEncodings::Html),
             "sam" => Ok(BlockEncodings::Sam),
+            "djot" => Ok(BlockEncodings::Djot),
+            "text" => Ok(BlockEncodings::Text),
+            "code" => Ok(BlockEncodings::Code(None)),
             _ => Err(format!("'{}' is not a valid value for Formats", s)),
         }
     }
 }
+impl std::fmt::Display for BlockEncodings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockEncodings::Json => write!(f, "json"),
+            BlockEncodings::Toml => write!(f, "toml"),
+            BlockEncodings::Yaml => write!(f, "yaml"),
+            BlockEncodings::Markdown => write!(f, "markdown"),
+            BlockEncodings::Html => write!(f, "html"),
+            BlockEncodings::Sam => write!(f, "sam"),
+            BlockEncodings::Djot => write!(f, "djot"),
+            BlockEncodings::Text => write!(f, "text"),
+            BlockEncodings::Code(Some(language)) => write!(f, "code:{}", language),
+            BlockEncodings::Code(None) => write!(f, "code"),
+        }
+    }
+}
 
+/// The root of a block path: a built-in type, or a type declared in config.
 #[derive(Debug)]
 enum BlockType {
     Metadata,
     Post,
     Page,
+    Custom(String),
 }
 impl BlockType {
-    fn as_str(&self) -> &'static str {
+    fn as_str(&self) -> &str {
         match self {
             BlockType::Metadata => "metadata",
             BlockType::Post => "post",
             BlockType::Page => "page",
+            BlockType::Custom(name) => name,
         }
     }
 }
@@ -212,15 +706,58 @@ impl std::str::FromStr for BlockType {
             "metadata" => Ok(BlockType::Metadata),
             "post" => Ok(BlockType::Post),
             "page" => Ok(BlockType::Page),
+            _ if !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') =>
+            {
+                Ok(BlockType::Custom(String::from(s)))
+            }
             _ => Err(format!("'{}' is not a valid value for DataType", s)),
         }
     }
 }
 
+/// One step in a block path: an object key, or a position in an array.
+#[derive(Debug, Clone, PartialEq)]
+enum PathSegment {
+    Key(String),
+    /// `[n]`, an existing element or the one just past the end
+    Index(usize),
+    /// `[]`, a new element at the end
+    Append,
+}
+impl std::fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathSegment::Key(key) => write!(f, ".{}", key),
+            PathSegment::Index(index) => write!(f, "[{}]", index),
+            PathSegment::Append => write!(f, "[]"),
+        }
+    }
+}
+
 #[derive(Debug)]
 struct BlockPath {
     block_type: BlockType,
-    path: Vec<String>,
+    path: Vec<PathSegment>,
+}
+impl BlockPath {
+    /// The full path from the block type, split into its parents and leaf.
+    fn components(&self) -> (Vec<PathSegment>, Option<PathSegment>) {
+        let mut path = vec![PathSegment::Key(String::from(self.block_type.as_str()))];
+        path.extend(self.path.iter().cloned());
+        let last = path.pop();
+        (path, last)
+    }
+}
+impl std::fmt::Display for BlockPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.block_type.as_str())?;
+        for segment in &self.path {
+            write!(f, "{}", segment)?;
+        }
+        Ok(())
+    }
 }
 impl std::str::FromStr for BlockPath {
     type Err = String;
@@ -237,12 +774,28 @@ impl std::str::FromStr for BlockPath {
             }
         };
 
-        let mut path: Vec<String> = vec![];
+        let mut path: Vec<PathSegment> = vec![];
         while let Some(component) = components.next() {
-            if component.is_empty() {
+            // a key, followed by any number of `[]` or `[n]` suffixes
+            let (key, mut indices) =
+                component.split_at(component.find('[').unwrap_or(component.len()));
+            if key.is_empty() {
                 return Err(format!("Found empty component in block path '{}'", s));
             }
-            path.push(String::from(component));
+            path.push(PathSegment::Key(String::from(key)));
+            while !indices.is_empty() {
+                let (index, rest) = indices
+                    .strip_prefix('[')
+                    .and_then(|indices| indices.split_once(']'))
+                    .ok_or_else(|| format!("Found malformed index in block path '{}'", s))?;
+                path.push(match index {
+                    "" => PathSegment::Append,
+                    _ => PathSegment::Index(index.parse().map_err(|_| {
+                        format!("'{}' is not a valid index in block path '{}'", index, s)
+                    })?),
+                });
+                indices = rest;
+            }
         }
 
         Ok(BlockPath { block_type, path })
@@ -252,35 +805,361 @@ impl std::str::FromStr for BlockPath {
 #[derive(Debug)]
 enum BlockContent {
     Json(JsonString),
+    Toml(TomlString),
+    Yaml(YamlString),
     Markdown(MarkdownString),
     Html(HtmlString),
     Sam(SamString),
+    Djot(DjotString),
+    Text(TextString),
+    Code(CodeString),
 }
 impl BlockContent {
     fn transform(encoding: &BlockEncoding, content: String) -> BlockContent {
-        match encoding.encoding {
+        match &encoding.encoding {
             BlockEncodings::Json => BlockContent::Json(JsonString { content }),
+            BlockEncodings::Toml => BlockContent::Toml(TomlString { content }),
+            BlockEncodings::Yaml => BlockContent::Yaml(YamlString { content }),
             BlockEncodings::Markdown => BlockContent::Markdown(MarkdownString { content }),
-            BlockEncodings::Html => match encoding.source {
+            BlockEncodings::Html => match &encoding.source {
                 Some(BlockEncodings::Markdown) => {
                     BlockContent::Html((MarkdownString { content }).into())
                 }
                 Some(BlockEncodings::Sam) => BlockContent::Html((SamString { content }).into()),
+                Some(BlockEncodings::Djot) => BlockContent::Html((DjotString { content }).into()),
+                Some(BlockEncodings::Text) => BlockContent::Html((TextString { content }).into()),
+                Some(BlockEncodings::Code(language)) => BlockContent::Html(
+                    (CodeString {
+                        content,
+                        language: language.clone(),
+                    })
+                    .into(),
+                ),
                 _ => BlockContent::Html(HtmlString { content }),
             },
             BlockEncodings::Sam => BlockContent::Sam(SamString { content }),
+            BlockEncodings::Djot => BlockContent::Djot(DjotString { content }),
+            BlockEncodings::Text => BlockContent::Text(TextString { content }),
+            BlockEncodings::Code(language) => BlockContent::Code(CodeString {
+                content,
+                language: language.clone(),
+            }),
         }
     }
 }
+impl BlockContent {
+    fn templated(encoding: &BlockEncoding, content: String) -> BlockContent {
+        let source = match (&encoding.source, &encoding.encoding) {
+            (Some(BlockEncodings::Markdown), BlockEncodings::Html) => TemplatedSource::Markdown,
+            (Some(BlockEncodings::Sam), BlockEncodings::Html) => TemplatedSource::Sam,
+            (Some(BlockEncodings::Djot), BlockEncodings::Html) => TemplatedSource::Djot,
+            (Some(BlockEncodings::Text), BlockEncodings::Html) => TemplatedSource::Text,
+            _ => TemplatedSource::Verbatim,
+        };
+        let content = templated::placeholder(source, &content);
+        BlockContent::Html(HtmlString { content })
+    }
+}
 impl TryFrom<BlockContent> for serde_json::Value {
     type Error = Error;
 
     fn try_from(value: BlockContent) -> Result<Self, Self::Error> {
         match value {
             BlockContent::Json(json) => json.try_into(),
+            BlockContent::Toml(toml) => toml.try_into(),
+            BlockContent::Yaml(yaml) => yaml.try_into(),
             BlockContent::Markdown(md) => Ok(md.into()),
             BlockContent::Html(html) => Ok(html.into()),
             BlockContent::Sam(sam) => Ok(sam.into()),
+            BlockContent::Djot(djot) => Ok(djot.into()),
+            BlockContent::Text(text) => Ok(text.into()),
+            BlockContent::Code(code) => Ok(code.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Blocks, Error, Includes};
+    use serde_json::json;
+
+    fn metadata() -> serde_json::Value {
+        json!({
+            "author_name": "jakintosh",
+            "published_date": "2022-01-03",
+            "version": 2,
+            "build": true,
+            "tags": ["rust", "web"],
+            "attachments": [{ "url": "a.mp3", "mime_type": "audio/mpeg" }],
+        })
+    }
+
+    fn parse(encoding: &str, content: &str) -> serde_json::Value {
+        let blocks: Blocks = format!("post.metadata:{}\n+++\n{}+++\n", encoding, content)
+            .parse()
+            .unwrap();
+        let json: serde_json::Value = blocks.try_into().unwrap();
+        json["post"]["metadata"].clone()
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        // via toml::Value, which writes tables after plain values
+        let toml = toml::Value::try_from(metadata()).unwrap().to_string();
+        assert_eq!(parse("toml", &toml), metadata());
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let yaml = serde_yaml::to_string(&metadata()).unwrap();
+        assert_eq!(parse("yaml", &yaml), metadata());
+    }
+
+    #[test]
+    fn test_text_and_code_to_html() {
+        let blocks: Blocks = "post.poem:text->html\n+++\n  roses & <violets>\n\tare blue\n\nfin\n+++\npost.listing:code:rust->html\n+++\nlet x = a < b;\n+++\n"
+            .parse()
+            .unwrap();
+        let json: serde_json::Value = blocks.try_into().unwrap();
+        assert_eq!(
+            json["post"]["poem"],
+            "<p>&nbsp;&nbsp;roses &amp; &lt;violets&gt;<br />\n&nbsp;&nbsp;&nbsp;&nbsp;are blue</p>\n<p>fin</p>\n"
+        );
+        assert_eq!(
+            json["post"]["listing"],
+            "<pre><code class=\"language-rust\">let x = a &lt; b;\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn test_djot_to_html() {
+        let blocks: Blocks = "post.notes:djot->html\n+++\n: term\n\n  See [[garden]], not `[[code]]`.\n\n{.lead}\nIntro.\n\n::: warning\nCareful.\n:::\n+++\n"
+            .parse()
+            .unwrap();
+        let json: serde_json::Value = blocks.try_into().unwrap();
+        let html = json["post"]["notes"].as_str().unwrap();
+        assert!(html.contains("<dl>"));
+        assert!(html.contains("<dt>term</dt>"));
+        assert!(html.contains("See <a class=\"wikilink\" href=\"wikilink:garden\"></a>, not"));
+        assert!(html.contains("<code>[[code]]</code>"));
+        assert!(html.contains("<p class=\"lead\">Intro.</p>"));
+        assert!(html.contains("<div class=\"warning\">"));
+        assert!(html.contains("<p>Careful.</p>"));
+    }
+
+    #[test]
+    fn test_array_paths() {
+        let blocks: Blocks = "post.sections[]:markdown\n+++\none\n+++\npost.sections[]:json\n+++\n{ \"title\": \"two\" }\n+++\npost.sections[1].body:text\n+++\nbody\n+++\npost.grid[0][0]:json\n+++\n1\n+++\n"
+            .parse()
+            .unwrap();
+        let json: serde_json::Value = blocks.try_into().unwrap();
+        assert_eq!(
+            json["post"],
+            json!({
+                "sections": ["one\n", { "title": "two", "body": "body\n" }],
+                "grid": [[1]],
+            })
+        );
+
+        let blocks: Blocks = "post.sections[1]:text\n+++\nskipped\n+++\n"
+            .parse()
+            .unwrap();
+        assert!(serde_json::Value::try_from(blocks).is_err());
+    }
+
+    #[test]
+    fn test_conflicting_blocks() {
+        let conflict = |modifier: &str| {
+            format!(
+                "post:json\n+++\n{{ \"title\": \"One\", \"tags\": {{ \"a\": 1 }} }}\n+++\npost:json {}\n+++\n{{ \"title\": \"Two\", \"tags\": {{ \"b\": 2 }} }}\n+++\n",
+                modifier
+            )
+            .parse::<Blocks>()
+            .unwrap()
+            .try_into()
+        };
+
+        let error: Result<serde_json::Value, _> = conflict("");
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "Block at line 5 overwrites 'post.tags', which was set by the block at line 1; add '!merge' or '!replace' to its header if that's intended"
+        );
+        let merged: serde_json::Value = conflict("!merge").unwrap();
+        assert_eq!(
+            merged["post"],
+            json!({ "title": "Two", "tags": { "a": 1, "b": 2 } })
+        );
+        let replaced: serde_json::Value = conflict("!replace").unwrap();
+        assert_eq!(
+            replaced["post"],
+            json!({ "title": "Two", "tags": { "b": 2 } })
+        );
+    }
+
+    #[test]
+    fn test_merge_replaces_non_objects() {
+        let blocks: Blocks = "post.tags:json\n+++\n[\"a\"]\n+++\npost.tags:json !merge\n+++\n[\"b\"]\n+++\npost.title:text\n+++\nOne\n+++\npost.title:json !merge\n+++\n{ \"text\": \"Two\" }\n+++\n"
+            .parse()
+            .unwrap();
+        let json: serde_json::Value = blocks.try_into().unwrap();
+        assert_eq!(
+            json["post"],
+            json!({ "tags": ["b"], "title": { "text": "Two" } })
+        );
+    }
+
+    #[test]
+    fn test_conflicting_array_paths() {
+        let conflict = |blocks: &str| {
+            let blocks: Blocks = blocks.parse().unwrap();
+            serde_json::Value::try_from(blocks).unwrap_err().to_string()
+        };
+
+        assert_eq!(
+            conflict("post.sections[]:json\n+++\n{ \"title\": \"one\" }\n+++\npost.sections[0].title:text\n+++\ntwo\n+++\n"),
+            "Block at line 5 overwrites 'post.sections[0].title', which was set by the block at line 1; add '!merge' or '!replace' to its header if that's intended"
+        );
+        assert_eq!(
+            conflict("post.grid[0][0]:json\n+++\n1\n+++\npost.grid:json\n+++\n[2]\n+++\n"),
+            "Block at line 5 overwrites 'post.grid', which was set by the block at line 1; add '!merge' or '!replace' to its header if that's intended"
+        );
+    }
+
+    #[test]
+    fn test_longer_fences_contain_shorter_ones() {
+        let blocks: Blocks = "post.content:markdown\n++++\n+++\ntitle = \"Hugo\"\n+++\n++++\n"
+            .parse()
+            .unwrap();
+        let json: serde_json::Value = blocks.try_into().unwrap();
+        assert_eq!(json["post"]["content"], "+++\ntitle = \"Hugo\"\n+++\n");
+    }
+
+    #[test]
+    fn test_to_text_is_canonical() {
+        let messy = "post.metadata:json\n\n++++\n{\"b\":[],\"a\":{\"c\":\"{,}\"}}\n++++\npost.content:markdown->html   !template\n+++\n  kept  \r\n+++\n\n\npost.summary:text < ./summary.txt\n";
+        let canonical = "post.metadata:json\n+++\n{\n  \"b\": [],\n  \"a\": {\n    \"c\": \"{,}\"\n  }\n}\n+++\n\npost.content:markdown->html !template\n+++\n  kept  \r\n+++\n\npost.summary:text < ./summary.txt\n";
+        let blocks = Blocks::parse(messy, &mut Includes::skipped()).unwrap();
+        assert_eq!(blocks.to_text(), canonical);
+        let blocks = Blocks::parse(canonical, &mut Includes::skipped()).unwrap();
+        assert_eq!(blocks.to_text(), canonical);
+    }
+
+    /// Writes `files` into a fresh directory under the system temp directory.
+    fn fixture(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("site-builder-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn parse_fixture(dir: &std::path::Path, path: &str) -> Result<Blocks, Error> {
+        let path = dir.join(path);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let blocks = contents.split_once('\n').unwrap().1;
+        Blocks::parse(blocks, &mut Includes::new(&path))
+    }
+
+    #[test]
+    fn test_includes_resolve_relative_to_their_file() {
+        let dir = fixture(
+            "includes",
+            &[
+                ("posts/a.post", "type::post\npost.intro:text < ../shared/intro.txt\npost.content:text < ../shared/b.post#post.content\n"),
+                ("shared/intro.txt", "intro\n"),
+                // a byte order mark and blank lines may come before the type
+                ("shared/b.post", "\u{feff}\n\ntype::post\npost.content:text < ./note.txt\n"),
+                ("shared/note.txt", "note\n"),
+            ],
+        );
+        let blocks = parse_fixture(&dir, "posts/a.post").unwrap();
+        let sources = blocks.sources();
+        let json: serde_json::Value = blocks.try_into().unwrap();
+        assert_eq!(json["post"]["intro"], "intro\n");
+        assert_eq!(json["post"]["content"], "note\n");
+
+        let files: Vec<&str> = sources[1]
+            .includes
+            .iter()
+            .map(|file| file.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(files, ["b.post", "note.txt"]);
+        // absolute paths stay out of the api
+        assert!(serde_json::to_value(&sources[1])
+            .unwrap()
+            .get("includes")
+            .is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let dir = fixture(
+            "include-errors",
+            &[
+                (
+                    "a.post",
+                    "type::post\npost.content:text < ./b.post#post.content\n",
+                ),
+                (
+                    "b.post",
+                    "type::post\npost.content:text < ./a.post#post.content\n",
+                ),
+                ("c.post", "type::post\npost.content:text\n+++\nc\n+++\n"),
+                (
+                    "d.post",
+                    "type::post\npost.content:text < ./c.post#post.missing\n",
+                ),
+            ],
+        );
+        match parse_fixture(&dir, "a.post") {
+            Err(Error::IncludeCycleError { chain }) => {
+                let files: Vec<&str> = chain
+                    .split(" -> ")
+                    .map(|file| file.rsplit('/').next().unwrap())
+                    .collect();
+                assert_eq!(files, ["a.post", "b.post", "a.post"]);
+            }
+            _ => panic!("expected an include cycle"),
+        }
+        match parse_fixture(&dir, "d.post") {
+            Err(Error::MissingIncludedBlockError { path, block_path }) => {
+                assert!(path.ends_with("c.post"));
+                assert_eq!(block_path, "post.missing");
+            }
+            _ => panic!("expected a missing included block"),
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_types_without_fields_take_any_field() {
+        let dir = fixture(
+            "content-types",
+            &[(
+                "a.project",
+                "type::project\nproject:json\n+++\n{ \"title\": \"A\", \"year\": 2022 }\n+++\n",
+            )],
+        );
+        let content_type = |fields: serde_json::Value| -> crate::parsing::SiteContentType {
+            let mut content_type = json!({ "name": "project", "content_template": "project.tera" });
+            content_type["fields"] = fields;
+            serde_json::from_value(content_type).unwrap()
+        };
+        let parse = |fields| {
+            crate::parsing::parse_blocks_file(dir.join("a.project"), &[content_type(fields)])
+        };
+
+        match parse(serde_json::Value::Null) {
+            Ok(crate::parsing::Content::Entry(entry)) => assert_eq!(entry.fields["year"], 2022),
+            _ => panic!("expected an entry"),
         }
+        assert!(parse(json!([{ "name": "title", "type": "string" }])).is_err());
+        assert!(parse(json!([{ "name": "title" }, { "name": "year" }])).is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,243 @@
+use super::blocks::fence_for;
+use super::{schema, Error, TomlString, YamlString};
+use serde_json::{json, Map, Value};
+
+/// A post read from markdown with Hugo, Jekyll or Zola style front matter.
+pub(crate) struct FrontMatterPost {
+    /// The post's json, as `type::post` blocks would build it, with the
+    /// content still in markdown.
+    pub post: Value,
+    /// Front matter fields that have no equivalent on a post.
+    pub unmapped: Vec<String>,
+}
+
+/// Reads `---` delimited YAML or `+++` delimited TOML front matter and maps
+/// it onto a post's fields. `file_stem` stands in for a missing slug (and, in
+/// the Jekyll `2022-01-03-slug` form, for a missing date), and `author` for a
+/// missing author.
+pub(crate) fn parse_front_matter(
+    contents: &str,
+    file_stem: &str,
+    author: Option<&str>,
+) -> Result<FrontMatterPost, Error> {
+    let (front_matter, markdown) = split_front_matter(contents)?;
+    let fields = match front_matter {
+        FrontMatter::Yaml(content) => YamlString { content }.try_into()?,
+        FrontMatter::Toml(content) => TomlString { content }.try_into()?,
+    };
+    let mut fields = match fields {
+        Value::Object(fields) => fields,
+        Value::Null => Map::new(),
+        _ => {
+            return Err(Error::MalformedFrontMatterError {
+                reason: String::from("front matter must be a table of fields"),
+            })
+        }
+    };
+
+    let (stem_date, stem_slug) = split_dated_stem(file_stem);
+    let mut post = Map::new();
+    let mut metadata = Map::new();
+    let take = |fields: &mut Map<String, Value>, names: &[&str]| {
+        names.iter().find_map(|name| fields.remove(*name))
+    };
+
+    if let Some(title) = take(&mut fields, &["title"]) {
+        post.insert(String::from("title"), title);
+    }
+    let slug = take(&mut fields, &["slug"]).unwrap_or_else(|| Value::from(stem_slug));
+    // the slug names the block file, so it can't leave the directory
+    if slug
+        .as_str()
+        .is_some_and(|slug| slug.is_empty() || slug.contains('/'))
+    {
+        return Err(Error::MalformedFrontMatterError {
+            reason: format!("{} isn't a usable slug", slug),
+        });
+    }
+    post.insert(String::from("slug"), slug);
+    let author_name = match take(&mut fields, &["author", "authors"]) {
+        Some(Value::Array(authors)) => authors.into_iter().next(),
+        author => author,
+    };
+    if let Some(author_name) = author_name.or_else(|| author.map(Value::from)) {
+        metadata.insert(String::from("author_name"), author_name);
+    }
+    if let Some(date) = take(&mut fields, &["date"]).or_else(|| stem_date.map(Value::from)) {
+        metadata.insert(String::from("published_date"), date_only(date));
+    }
+    if let Some(date) = take(&mut fields, &["lastmod", "updated", "last_modified_at"]) {
+        metadata.insert(String::from("updated_date"), date_only(date));
+    }
+    // Hugo and Zola mark drafts, Jekyll marks what's published
+    let draft = take(&mut fields, &["draft"]);
+    match (draft, take(&mut fields, &["published"])) {
+        (Some(Value::Bool(true)), _) | (_, Some(Value::Bool(false))) => {
+            metadata.insert(String::from("build"), Value::Bool(false));
+        }
+        _ => {}
+    }
+    let tags = match take(&mut fields, &["tags"]) {
+        Some(tags) => Some(tags),
+        // Zola keeps tags among its taxonomies
+        None => match fields.get_mut("taxonomies") {
+            Some(Value::Object(taxonomies)) => taxonomies.remove("tags"),
+            _ => None,
+        },
+    };
+    if let Some(tags) = tags {
+        let tags = match tags {
+            Value::String(tag) => json!([tag]),
+            tags => tags,
+        };
+        metadata.insert(String::from("tags"), tags);
+    }
+    let series = match take(&mut fields, &["series"]) {
+        Some(Value::Array(series)) => series.into_iter().next(),
+        series => series,
+    };
+    if let Some(series) = series {
+        metadata.insert(String::from("series"), series);
+    }
+    if let Some(Value::Object(taxonomies)) = fields.get("taxonomies") {
+        if taxonomies.is_empty() {
+            fields.remove("taxonomies");
+        }
+    }
+
+    post.insert(String::from("metadata"), Value::Object(metadata));
+    post.insert(String::from("content"), Value::String(markdown));
+    let post = Value::Object(post);
+    let violations = schema::validate(&post, &schema::builtin_fields("post"), "post", false, &[]);
+    if !violations.is_empty() {
+        return Err(Error::SchemaViolationError {
+            content_type: String::from("post"),
+            violations: violations
+                .iter()
+                .map(|violation| format!("  {}", violation))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        });
+    }
+
+    Ok(FrontMatterPost {
+        post,
+        unmapped: fields.keys().cloned().collect(),
+    })
+}
+
+impl FrontMatterPost {
+    /// Writes the post as a native block file.
+    pub(crate) fn to_blocks(&self) -> String {
+        let json = |value: &Value| serde_json::to_string_pretty(value).unwrap_or_default();
+        let content = self.post["content"].as_str().unwrap_or_default();
+        format!(
+            "type::post\n\
+            post.metadata:json\n+++\n{}\n+++\n\n\
+            post.title:json\n+++\n{}\n+++\n\n\
+            post.slug:json\n+++\n{}\n+++\n\n\
+            post.content:markdown->html\n{fence}\n{}\n{fence}\n",
+            json(&self.post["metadata"]),
+            json(&self.post["title"]),
+            json(&self.post["slug"]),
+            content.trim_matches('\n'),
+            fence = fence_for(content),
+        )
+    }
+}
+
+enum FrontMatter {
+    Yaml(String),
+    Toml(String),
+}
+
+fn split_front_matter(contents: &str) -> Result<(FrontMatter, String), Error> {
+    let mut lines = contents.lines();
+    let delimiter = match lines.next().map(str::trim_end) {
+        Some(delimiter @ ("---" | "+++")) => delimiter,
+        _ => {
+            return Err(Error::MalformedFrontMatterError {
+                reason: String::from("expected '---' or '+++' on the first line"),
+            })
+        }
+    };
+
+    let mut front_matter = String::new();
+    for line in lines.by_ref() {
+        // Jekyll also allows YAML's document end marker
+        if line.trim_end() == delimiter || (delimiter == "---" && line.trim_end() == "...") {
+            let markdown = lines.collect::<Vec<_>>().join("\n");
+            let front_matter = match delimiter {
+                "---" => FrontMatter::Yaml(front_matter),
+                _ => FrontMatter::Toml(front_matter),
+            };
+            return Ok((front_matter, markdown));
+        }
+        front_matter.push_str(line);
+        front_matter.push('\n');
+    }
+
+    Err(Error::MalformedFrontMatterError {
+        reason: format!("front matter isn't closed by '{}'", delimiter),
+    })
+}
+
+/// Splits Jekyll's `2022-01-03-slug` file names into their date and slug.
+fn split_dated_stem(stem: &str) -> (Option<&str>, &str) {
+    let is_date = |date: &str| {
+        date.len() == 10
+            && date.char_indices().all(|(i, c)| {
+                if i == 4 || i == 7 {
+                    c == '-'
+                } else {
+                    c.is_ascii_digit()
+                }
+            })
+    };
+    match (stem.get(..10), stem.get(10..)) {
+        (Some(date), Some(rest)) if is_date(date) && rest.starts_with('-') => {
+            (Some(date), &rest[1..])
+        }
+        _ => (None, stem),
+    }
+}
+
+/// Front matter dates are often full timestamps; posts only keep the day.
+fn date_only(date: Value) -> Value {
+    match date {
+        Value::String(date) => {
+            let day = date.split(['T', ' ']).next().unwrap_or(&date);
+            Value::from(day)
+        }
+        date => date,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_front_matter;
+    use crate::parsing::blocks::Blocks;
+    use serde_json::json;
+
+    #[test]
+    fn test_front_matter_maps_to_post() {
+        let hugo = "+++\ntitle = \"Old\"\ndate = 2019-05-01T10:00:00Z\ndraft = true\ntags = [\"a\"]\n[params]\nx = 1\n+++\nBody\n\n+++\n";
+        let jekyll = "---\ntitle: Old\nauthor: Ada\ntags: a\n---\nBody\n\n+++\n";
+        let hugo = parse_front_matter(hugo, "old", Some("Ada")).unwrap();
+        let jekyll = parse_front_matter(jekyll, "2019-05-01-old", None).unwrap();
+        assert_eq!(hugo.unmapped, ["params"]);
+        assert_eq!(hugo.post["metadata"]["build"], false);
+
+        let metadata =
+            json!({ "author_name": "Ada", "published_date": "2019-05-01", "tags": ["a"] });
+        assert_eq!(jekyll.post["metadata"], metadata);
+        assert_eq!(jekyll.post["slug"], "old");
+
+        // the block file builds the same post back
+        let blocks = jekyll.to_blocks();
+        let (_, blocks) = blocks.split_once('\n').unwrap();
+        let json: serde_json::Value = blocks.parse::<Blocks>().unwrap().try_into().unwrap();
+        assert_eq!(json["post"]["metadata"], metadata);
+        assert_eq!(json["post"]["content"], "<p>Body</p>\n<p>+++</p>\n");
+    }
+}
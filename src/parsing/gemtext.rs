@@ -0,0 +1,403 @@
+use super::shortcodes::{self, split_shortcodes};
+use super::wikilinks::{self, gemtext_target, split_wikilinks};
+use super::{djot_text, CodeString, DjotString, GemtextString, MarkdownString, TextString};
+use crate::images;
+use jotdown::{Container, Event as DjotEvent};
+use pulldown_cmark::{CodeBlockKind, CowStr, Event, HeadingLevel, LinkType, Tag};
+
+/// Converts markdown to gemtext.
+///
+/// Gemtext has no inline markup, so emphasis is dropped and every link or
+/// image in a block is hoisted to its own `=>` line after that block.
+/// Wikilinks are hoisted the same way, and shortcodes (which render html
+/// components) are dropped. Relative links to `.html` pages point at their
+/// `.gmi` counterparts instead, and images from `images/` are left for the
+/// renderer to copy into the capsule.
+impl From<MarkdownString> for GemtextString {
+    fn from(markdown: MarkdownString) -> Self {
+        let mut writer = GemtextWriter::default();
+        for event in pulldown_cmark::Parser::new(&markdown.content) {
+            writer.push(event);
+        }
+        GemtextString {
+            content: writer.finish(),
+        }
+    }
+}
+
+/// Converts djot to gemtext, by mapping its events onto the markdown events
+/// they correspond to. Raw output is dropped, verbatim and math are kept as
+/// inline code, and containers gemtext has no form for keep only their text.
+impl From<DjotString> for GemtextString {
+    fn from(djot: DjotString) -> Self {
+        let mut writer = GemtextWriter::default();
+        let mut verbatim: Option<String> = None;
+        let mut raw_depth = 0;
+        for event in jotdown::Parser::new(&djot.content) {
+            let event = match event {
+                DjotEvent::Start(Container::RawBlock { .. } | Container::RawInline { .. }, _) => {
+                    raw_depth += 1;
+                    continue;
+                }
+                DjotEvent::End(Container::RawBlock { .. } | Container::RawInline { .. }) => {
+                    raw_depth -= 1;
+                    continue;
+                }
+                _ if raw_depth > 0 => continue,
+                DjotEvent::Start(Container::Verbatim | Container::Math { .. }, _) => {
+                    verbatim = Some(String::new());
+                    continue;
+                }
+                DjotEvent::End(Container::Verbatim | Container::Math { .. }) => {
+                    Event::Code(CowStr::from(verbatim.take().unwrap_or_default()))
+                }
+                DjotEvent::Str(text) if verbatim.is_some() => {
+                    // unwrapped because we just checked it's Some
+                    verbatim.as_mut().unwrap().push_str(&text);
+                    continue;
+                }
+                // cells run together on their row's line
+                DjotEvent::End(Container::TableCell { .. }) => Event::Text(CowStr::from(" ")),
+                DjotEvent::Start(container, _) => match djot_tag(&container) {
+                    Some(tag) => Event::Start(tag),
+                    None => continue,
+                },
+                DjotEvent::End(container) => match djot_tag(&container) {
+                    Some(tag) => Event::End(tag),
+                    None => continue,
+                },
+                DjotEvent::Softbreak => Event::SoftBreak,
+                DjotEvent::Hardbreak => Event::HardBreak,
+                DjotEvent::ThematicBreak(_) => Event::Rule,
+                DjotEvent::NonBreakingSpace => Event::Text(CowStr::from(" ")),
+                DjotEvent::Symbol(symbol) => Event::Text(CowStr::from(format!(":{}:", symbol))),
+                DjotEvent::FootnoteReference(label) => {
+                    Event::Text(CowStr::from(format!("[^{}]", label)))
+                }
+                event => match djot_text(&event) {
+                    Some(text) => Event::Text(CowStr::from(String::from(text))),
+                    None => continue,
+                },
+            };
+            writer.push(event);
+        }
+        GemtextString {
+            content: writer.finish(),
+        }
+    }
+}
+
+/// The markdown tag a djot container corresponds to, if gemtext has a form
+/// for it.
+fn djot_tag(container: &Container) -> Option<Tag<'static>> {
+    let tag = match container {
+        Container::Paragraph
+        | Container::DescriptionTerm
+        | Container::Caption
+        | Container::TableRow { .. } => Tag::Paragraph,
+        Container::Heading { level, .. } => {
+            let level = match level {
+                1 => HeadingLevel::H1,
+                2 => HeadingLevel::H2,
+                _ => HeadingLevel::H3,
+            };
+            Tag::Heading(level, None, Vec::new())
+        }
+        Container::Blockquote => Tag::BlockQuote,
+        Container::List { .. } => Tag::List(None),
+        Container::ListItem | Container::TaskListItem { .. } => Tag::Item,
+        Container::CodeBlock { language, .. } => {
+            Tag::CodeBlock(CodeBlockKind::Fenced(CowStr::from(language.to_string())))
+        }
+        Container::Link(destination, _) => Tag::Link(
+            LinkType::Inline,
+            CowStr::from(destination.to_string()),
+            CowStr::from(""),
+        ),
+        Container::Image(destination, _) => Tag::Image(
+            LinkType::Inline,
+            CowStr::from(destination.to_string()),
+            CowStr::from(""),
+        ),
+        _ => return None,
+    };
+    Some(tag)
+}
+
+/// Plain text is already line-oriented, so it carries over as is, except
+/// that lines which gemtext would read as links, headings, list items, quotes
+/// or preformatting toggles are indented by a space.
+impl From<TextString> for GemtextString {
+    fn from(text: TextString) -> Self {
+        let mut content = String::new();
+        for line in text.content.trim_matches('\n').lines() {
+            if ["=>", "#", "*", ">", "```"]
+                .iter()
+                .any(|prefix| line.starts_with(prefix))
+            {
+                content.push(' ');
+            }
+            content.push_str(line);
+            content.push('\n');
+        }
+        GemtextString { content }
+    }
+}
+
+/// Code becomes a preformatted block, with its language as the alt text.
+impl From<CodeString> for GemtextString {
+    fn from(code: CodeString) -> Self {
+        let content = format!(
+            "```{}\n{}\n```\n",
+            code.language.unwrap_or_default(),
+            code.content.trim_end_matches('\n')
+        );
+        GemtextString { content }
+    }
+}
+
+#[derive(Default)]
+struct GemtextWriter {
+    output: String,
+    text: String,
+    line: String,
+    links: Vec<(String, String)>,
+    link_text: Option<String>,
+    quote_depth: usize,
+    list_depth: usize,
+    in_code_block: bool,
+}
+impl GemtextWriter {
+    fn push(&mut self, event: Event) {
+        // coalesce text, since the parser splits it around brackets
+        if let Event::Text(text) = &event {
+            if !self.in_code_block {
+                self.text.push_str(text);
+                return;
+            }
+        }
+        self.flush_text();
+
+        match event {
+            Event::Start(Tag::Heading(level, ..)) => {
+                self.line.push_str(match level {
+                    HeadingLevel::H1 => "# ",
+                    HeadingLevel::H2 => "## ",
+                    _ => "### ",
+                });
+            }
+            Event::Start(Tag::Item) => {
+                self.end_line();
+                self.line.push_str("* ");
+            }
+            Event::Start(Tag::BlockQuote) => self.quote_depth += 1,
+            Event::End(Tag::BlockQuote) => self.quote_depth -= 1,
+            Event::Start(Tag::List(_)) => {
+                // a nested list starts below its parent item's text
+                self.end_line();
+                self.list_depth += 1;
+            }
+            Event::End(Tag::List(_)) => {
+                self.list_depth -= 1;
+                if self.list_depth == 0 {
+                    self.end_block();
+                }
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                self.in_code_block = true;
+                let alt = match kind {
+                    CodeBlockKind::Fenced(lang) => lang.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.output.push_str(&format!("```{}\n", alt));
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                self.in_code_block = false;
+                if !self.output.ends_with('\n') {
+                    self.output.push('\n');
+                }
+                self.output.push_str("```\n\n");
+            }
+            Event::Start(Tag::Link(_, destination, _)) => {
+                self.links.push((capsule_link(&destination), String::new()));
+                self.link_text = Some(String::new());
+            }
+            Event::Start(Tag::Image(_, destination, _)) => {
+                let target = match destination.trim_start_matches('/').strip_prefix("images/") {
+                    Some(src) => images::gemtext_target(src),
+                    None => destination.to_string(),
+                };
+                self.links.push((target, String::new()));
+                self.link_text = Some(String::new());
+            }
+            Event::End(Tag::Link(..) | Tag::Image(..)) => {
+                if let (Some(text), Some(link)) = (self.link_text.take(), self.links.last_mut()) {
+                    link.1 = text;
+                }
+            }
+            Event::End(Tag::Heading(..)) | Event::End(Tag::Paragraph) => self.end_block(),
+            Event::End(Tag::Item) => self.end_line(),
+            Event::Text(text) => self.output.push_str(&text),
+            Event::Code(text) => self.push_text(&text),
+            Event::SoftBreak => self.push_text(" "),
+            Event::HardBreak => self.end_line(),
+            Event::Rule => self.end_block(),
+            _ => {}
+        }
+    }
+
+    fn flush_text(&mut self) {
+        let text = std::mem::take(&mut self.text);
+        for segment in split_shortcodes(&text) {
+            let text = match segment {
+                shortcodes::Segment::Text(text) => text,
+                shortcodes::Segment::Shortcode(_) => continue,
+            };
+            for segment in split_wikilinks(text) {
+                match segment {
+                    wikilinks::Segment::Text(text) => self.push_text(text),
+                    wikilinks::Segment::Link { name, label } => {
+                        self.push_text(label.unwrap_or(name));
+                        let label = String::from(label.unwrap_or(""));
+                        self.links.push((gemtext_target(name), label));
+                    }
+                }
+            }
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if let Some(link_text) = &mut self.link_text {
+            link_text.push_str(text);
+        }
+        self.line.push_str(text);
+    }
+
+    fn end_line(&mut self) {
+        let line = self.line.trim_end();
+        if !line.is_empty() {
+            self.output.push_str(&"> ".repeat(self.quote_depth));
+            self.output.push_str(line);
+            self.output.push('\n');
+        }
+        self.line.clear();
+    }
+
+    fn end_block(&mut self) {
+        if self.list_depth > 0 {
+            return;
+        }
+        self.end_line();
+        for (url, text) in self.links.drain(..) {
+            match text.trim() {
+                "" => self.output.push_str(&format!("=> {}\n", url)),
+                text => self.output.push_str(&format!("=> {} {}\n", url, text)),
+            }
+        }
+        if !self.output.is_empty() && !self.output.ends_with("\n\n") {
+            self.output.push('\n');
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush_text();
+        self.end_block();
+        self.output.trim_end().to_owned() + "\n"
+    }
+}
+
+/// Points a relative link to an `.html` page at the page's `.gmi` in the
+/// capsule. Links to other sites are left as they are.
+fn capsule_link(destination: &str) -> String {
+    let is_relative = !destination.starts_with("//")
+        && !destination
+            .split(['/', '?', '#'])
+            .next()
+            .is_some_and(|first| first.contains(':'));
+    let end = destination.find(['?', '#']).unwrap_or(destination.len());
+    let (path, rest) = destination.split_at(end);
+    match path.strip_suffix(".html") {
+        Some(stem) if is_relative => format!("{}.gmi{}", stem, rest),
+        _ => String::from(destination),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DjotString, GemtextString, MarkdownString, TextString};
+
+    fn to_gemtext(markdown: &str) -> String {
+        let markdown = MarkdownString {
+            content: String::from(markdown),
+        };
+        GemtextString::from(markdown).content
+    }
+
+    #[test]
+    fn test_gemtext_hoists_links() {
+        let gemtext = to_gemtext("## Hello\n\nSee *the* [docs](https://example.com) now.\n");
+        assert_eq!(
+            gemtext,
+            "## Hello\n\nSee the docs now.\n=> https://example.com docs\n"
+        );
+    }
+
+    #[test]
+    fn test_gemtext_hoists_wikilinks_and_drops_shortcodes() {
+        let gemtext = to_gemtext("See [[garden]] and [[about|me]].\n\n{{< figure >}}\n");
+        assert_eq!(
+            gemtext,
+            "See garden and me.\n=> wikilink:garden\n=> wikilink:about me\n"
+        );
+    }
+
+    #[test]
+    fn test_gemtext_lists_and_code() {
+        let gemtext = to_gemtext("- one\n- [two](b.gmi)\n\n```rust\nfn main() {}\n```\n");
+        assert_eq!(
+            gemtext,
+            "* one\n* two\n=> b.gmi two\n\n```rust\nfn main() {}\n```\n"
+        );
+    }
+
+    #[test]
+    fn test_gemtext_nested_lists() {
+        let gemtext = to_gemtext("- one\n  - two\n  - three\n- four\n\nafter\n");
+        assert_eq!(gemtext, "* one\n* two\n* three\n* four\n\nafter\n");
+    }
+
+    #[test]
+    fn test_gemtext_rewrites_site_links() {
+        let gemtext = to_gemtext(
+            "[a](../posts/a.html#top) [b](https://example.com/b.html) ![c](images/c.png)\n",
+        );
+        assert_eq!(
+            gemtext,
+            "a b c\n=> ../posts/a.gmi#top a\n=> https://example.com/b.html b\n=> image:c.png c\n"
+        );
+    }
+
+    #[test]
+    fn test_gemtext_escapes_text_line_types() {
+        let text = TextString {
+            content: String::from(
+                "\n# not a heading\n=> not a link\n* not an item\n```\n  indented\n",
+            ),
+        };
+        assert_eq!(
+            GemtextString::from(text).content,
+            " # not a heading\n => not a link\n * not an item\n ```\n  indented\n"
+        );
+    }
+
+    #[test]
+    fn test_gemtext_from_djot() {
+        let djot = DjotString {
+            content: String::from("# Title\n\nSee [[garden]] and `[[code]]`.\n"),
+        };
+        assert_eq!(
+            GemtextString::from(djot).content,
+            "# Title\n\nSee garden and [[code]].\n=> wikilink:garden\n"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use super::blocks::Blocks;
+use super::{split_type_declaration, Error};
+use crate::files::{path_to_string, read_file_contents};
+use std::path::{Path, PathBuf};
+
+/// Content read from outside the block that included it.
+pub(crate) struct Included {
+    pub content: String,
+    /// The canonical path of every file the content was read from,
+    /// including transitively.
+    pub files: Vec<String>,
+}
+
+/// Resolves includes (`< ./essay.md` or `< ./other.post#post.content`)
+/// relative to the content file being parsed, keeping the chain of files
+/// being parsed so that cycles are caught rather than recursed into.
+#[derive(Default)]
+pub(crate) struct Includes {
+    stack: Vec<PathBuf>,
+    skip: bool,
+}
+impl Includes {
+    pub(crate) fn new(path: impl AsRef<Path>) -> Includes {
+        Includes {
+            stack: vec![canonical(path.as_ref())],
+            skip: false,
+        }
+    }
+
+    /// Leaves every include empty, for when only the blocks themselves matter.
+    pub(crate) fn skipped() -> Includes {
+        Includes {
+            stack: Vec::new(),
+            skip: true,
+        }
+    }
+
+    pub(crate) fn resolve(&mut self, target: &str) -> Result<Included, Error> {
+        if self.skip {
+            return Ok(Included {
+                content: String::new(),
+                files: Vec::new(),
+            });
+        }
+
+        let (file, block_path) = match target.split_once('#') {
+            Some((file, block_path)) => (file, Some(block_path)),
+            None => (target, None),
+        };
+        let path = match self.stack.last().and_then(|current| current.parent()) {
+            Some(dir) => dir.join(file),
+            None => PathBuf::from(file),
+        };
+        let contents = read_file_contents(&path).map_err(|e| Error::IncludeReadError {
+            source: e,
+            path: path_to_string(&path),
+        })?;
+        let canonical_path = canonical(&path);
+        let file = path_to_string(&canonical_path);
+
+        // a plain file is included whole
+        let block_path = match block_path {
+            Some(block_path) => block_path,
+            None => {
+                return Ok(Included {
+                    content: contents,
+                    files: vec![file],
+                })
+            }
+        };
+
+        // otherwise it's another content file, which is parsed for the block
+        if self.stack.contains(&canonical_path) {
+            let mut chain: Vec<String> = self.stack.iter().map(path_to_string).collect();
+            chain.push(file);
+            return Err(Error::IncludeCycleError {
+                chain: chain.join(" -> "),
+            });
+        }
+        let blocks = match split_type_declaration(&contents) {
+            Some((_, blocks, _)) => blocks,
+            None => &contents,
+        };
+        self.stack.push(canonical_path);
+        let blocks = Blocks::parse(blocks, self);
+        self.stack.pop();
+
+        let source = blocks?
+            .sources()
+            .into_iter()
+            .rev()
+            .find(|source| source.path == block_path)
+            .ok_or_else(|| Error::MissingIncludedBlockError {
+                path: file.clone(),
+                block_path: String::from(block_path),
+            })?;
+        let mut files = vec![file];
+        files.extend(source.includes);
+        Ok(Included {
+            content: source.content,
+            files,
+        })
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,251 @@
+use super::{BlockSource, ContentField};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// The json type a field's value must have.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FieldType {
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Array,
+    Object,
+}
+impl FieldType {
+    fn matches(&self, value: &Value) -> bool {
+        match self {
+            FieldType::String => value.is_string(),
+            FieldType::Number => value.is_number(),
+            FieldType::Integer => value.is_i64() || value.is_u64(),
+            FieldType::Boolean => value.is_boolean(),
+            FieldType::Array => value.is_array(),
+            FieldType::Object => value.is_object(),
+        }
+    }
+
+    /// A value of this type with nothing in it, as a placeholder.
+    pub(crate) fn empty_value(&self) -> Value {
+        match self {
+            FieldType::String => json!(""),
+            FieldType::Number | FieldType::Integer => json!(0),
+            FieldType::Boolean => json!(false),
+            FieldType::Array => json!([]),
+            FieldType::Object => json!({}),
+        }
+    }
+}
+
+/// A place where merged block json doesn't match its content type's fields.
+#[derive(Debug)]
+pub(crate) struct Violation {
+    pub path: String,
+    pub line: Option<usize>,
+    pub message: String,
+}
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}: {}", line, self.path, self.message),
+            None => write!(f, "{}: {}", self.path, self.message),
+        }
+    }
+}
+
+/// Checks `value` against `fields`, collecting every violation rather than
+/// stopping at the first. Undeclared fields are only violations if `strict`.
+///
+/// Each violation is attributed to the last block whose path contains it,
+/// since that's the block that set (or should have set) the value.
+pub(crate) fn validate(
+    value: &Value,
+    fields: &[ContentField],
+    path: &str,
+    strict: bool,
+    sources: &[BlockSource],
+) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    validate_fields(value, fields, path, strict, &mut violations);
+    for violation in &mut violations {
+        violation.line = sources
+            .iter()
+            .filter(|source| {
+                violation.path == source.path
+                    || violation.path.starts_with(&format!("{}.", source.path))
+                    || violation.path.starts_with(&format!("{}[", source.path))
+                    || source.path.starts_with(&format!("{}.", violation.path))
+            })
+            .last()
+            .map(|source| source.line);
+    }
+    violations
+}
+
+fn validate_fields(
+    value: &Value,
+    fields: &[ContentField],
+    path: &str,
+    strict: bool,
+    violations: &mut Vec<Violation>,
+) {
+    let object = match value {
+        Value::Object(object) => object,
+        _ => {
+            violations.push(Violation {
+                path: String::from(path),
+                line: None,
+                message: String::from("expected an object"),
+            });
+            return;
+        }
+    };
+
+    for field in fields {
+        let field_path = format!("{}.{}", path, field.name);
+        let value = match object.get(&field.name) {
+            Some(value) => value,
+            None => {
+                if field.required.unwrap_or(true) {
+                    violations.push(Violation {
+                        path: field_path,
+                        line: None,
+                        message: String::from("missing required field"),
+                    });
+                }
+                continue;
+            }
+        };
+        if let Some(field_type) = field.field_type {
+            if !field_type.matches(value) {
+                violations.push(Violation {
+                    path: field_path,
+                    line: None,
+                    message: format!("expected {}, found {}", describe(field_type), kind(value)),
+                });
+                continue;
+            }
+        }
+        if let Some(nested) = &field.fields {
+            match value {
+                Value::Array(items) => {
+                    for (index, item) in items.iter().enumerate() {
+                        let item_path = format!("{}[{}]", field_path, index);
+                        validate_fields(item, nested, &item_path, strict, violations);
+                    }
+                }
+                _ => validate_fields(value, nested, &field_path, strict, violations),
+            }
+        }
+    }
+
+    if strict {
+        for key in object.keys() {
+            if !fields.iter().any(|field| &field.name == key) {
+                violations.push(Violation {
+                    path: format!("{}.{}", path, key),
+                    line: None,
+                    message: String::from("field isn't declared by the content type"),
+                });
+            }
+        }
+    }
+}
+
+fn describe(field_type: FieldType) -> &'static str {
+    match field_type {
+        FieldType::String => "a string",
+        FieldType::Number => "a number",
+        FieldType::Integer => "an integer",
+        FieldType::Boolean => "a boolean",
+        FieldType::Array => "an array",
+        FieldType::Object => "an object",
+    }
+}
+
+fn kind(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+//
+// Built-in types
+
+fn metadata_fields() -> Value {
+    json!({ "name": "metadata", "type": "object", "fields": [
+        { "name": "content_name", "type": "string", "required": false },
+        { "name": "directory", "type": "string", "required": false },
+        { "name": "author_name", "type": "string" },
+        { "name": "published_date", "type": "string" },
+        { "name": "updated_date", "type": "string", "required": false },
+        { "name": "version", "type": "integer", "required": false },
+        { "name": "build", "type": "boolean", "required": false },
+        { "name": "series", "type": "string", "required": false },
+        { "name": "series_order", "type": "integer", "required": false },
+        { "name": "tags", "type": "array", "required": false },
+        { "name": "attachments", "type": "array", "required": false, "fields": [
+            { "name": "url", "type": "string" },
+            { "name": "mime_type", "type": "string" },
+            { "name": "title", "type": "string", "required": false },
+            { "name": "size_in_bytes", "type": "integer", "required": false },
+            { "name": "duration_in_seconds", "type": "integer", "required": false },
+        ]},
+    ]})
+}
+
+/// The fields of `type::post` and `type::page`, which must deserialize into
+/// `PostOption` and `PageOption`.
+pub(crate) fn builtin_fields(block_type: &str) -> Vec<ContentField> {
+    let mut fields = vec![
+        metadata_fields(),
+        json!({ "name": "title", "type": "string" }),
+        json!({ "name": "content", "type": "string" }),
+    ];
+    if block_type == "post" {
+        fields.push(json!({ "name": "slug", "type": "string" }));
+    }
+    // the declarations above are static, so they always deserialize
+    serde_json::from_value(Value::Array(fields)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{builtin_fields, validate};
+    use crate::parsing::BlockSource;
+    use serde_json::json;
+
+    fn source(path: &str, line: usize) -> BlockSource {
+        BlockSource {
+            header: String::new(),
+            path: String::from(path),
+            line,
+            content: String::new(),
+            includes: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_reports_every_violation_with_its_line() {
+        let post = json!({
+            "metadata": { "author_name": 7, "published_date": "2022-01-01" },
+            "title": "Hello",
+            "content": "<p>hi</p>",
+        });
+        let sources = [source("post.metadata", 1), source("post.title", 6)];
+        let violations = validate(&post, &builtin_fields("post"), "post", false, &sources);
+        let messages: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            messages,
+            [
+                "line 1: post.metadata.author_name: expected a string, found a number",
+                "post.slug: missing required field",
+            ]
+        );
+    }
+}
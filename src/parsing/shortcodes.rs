@@ -0,0 +1,381 @@
+use super::{djot_raw_html, djot_text, is_djot_code};
+use base64ct::{Base64Url, Encoding};
+use pulldown_cmark::{CowStr, Event, Tag};
+use serde_json::{Map, Value};
+use std::ops::Range;
+
+static SHORTCODE_OPEN: &str = "{{<";
+static SHORTCODE_CLOSE: &str = ">}}";
+static PLACEHOLDER_OPEN: &str = "<!--shortcode:";
+static PLACEHOLDER_CLOSE: &str = "-->";
+
+/// A component invocation written in content as `{{< name key="value" >}}`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Shortcode {
+    pub name: String,
+    pub args: Map<String, Value>,
+}
+
+//
+// Parse-time expansion
+//
+// Shortcodes become html comment placeholders carrying their name and
+// encoded arguments, so they survive as opaque html until the renderer
+// replaces them with the rendered component.
+
+pub(crate) fn expand_markdown_events<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+) -> impl Iterator<Item = Event<'a>> {
+    let mut expanded: Vec<Event<'a>> = Vec::new();
+    let mut text = String::new();
+    // where inline html was joined into `text`, so it's kept as html when it
+    // doesn't turn out to be part of a shortcode
+    let mut html_spans: Vec<Range<usize>> = Vec::new();
+    let mut in_code = false;
+
+    for event in events {
+        match event {
+            Event::Text(t) if !in_code => {
+                text.push_str(&t);
+                continue;
+            }
+            // `{{<figure src="a.png">}}` reads as inline html after the `{{`
+            Event::Html(html) if !in_code && text.ends_with("{{") && html.starts_with('<') => {
+                html_spans.push(text.len()..text.len() + html.len());
+                text.push_str(&html);
+                continue;
+            }
+            _ => flush_markdown_text(&mut text, &mut html_spans, &mut expanded),
+        }
+        match &event {
+            Event::Start(Tag::CodeBlock(_)) => in_code = true,
+            Event::End(Tag::CodeBlock(_)) => in_code = false,
+            _ => {}
+        }
+        expanded.push(event);
+    }
+    flush_markdown_text(&mut text, &mut html_spans, &mut expanded);
+
+    // a shortcode alone in a paragraph stands in for the whole block
+    let mut unwrapped: Vec<Event<'a>> = Vec::with_capacity(expanded.len());
+    let mut events = expanded.into_iter();
+    while let Some(event) = events.next() {
+        if let Event::Start(Tag::Paragraph) = event {
+            let mut paragraph = vec![event];
+            for event in events.by_ref() {
+                let end = matches!(event, Event::End(Tag::Paragraph));
+                paragraph.push(event);
+                if end {
+                    break;
+                }
+            }
+            match &paragraph[..] {
+                [_, Event::Html(html), _] if is_placeholder(html) => {
+                    unwrapped.push(paragraph.swap_remove(1))
+                }
+                _ => unwrapped.append(&mut paragraph),
+            }
+        } else {
+            unwrapped.push(event);
+        }
+    }
+
+    unwrapped.into_iter()
+}
+
+fn flush_markdown_text<'a>(
+    text: &mut String,
+    html_spans: &mut Vec<Range<usize>>,
+    events: &mut Vec<Event<'a>>,
+) {
+    if text.is_empty() {
+        return;
+    }
+    for segment in split_shortcodes(text) {
+        match segment {
+            Segment::Text(t) => {
+                let mut start = t.as_ptr() as usize - text.as_ptr() as usize;
+                let end = start + t.len();
+                for span in html_spans.iter() {
+                    if span.end <= start || span.start >= end {
+                        continue;
+                    }
+                    let html_start = span.start.max(start);
+                    let html_end = span.end.min(end);
+                    if start < html_start {
+                        let t = String::from(&text[start..html_start]);
+                        events.push(Event::Text(CowStr::from(t)));
+                    }
+                    let html = String::from(&text[html_start..html_end]);
+                    events.push(Event::Html(CowStr::from(html)));
+                    start = html_end;
+                }
+                if start < end {
+                    events.push(Event::Text(CowStr::from(String::from(&text[start..end]))));
+                }
+            }
+            Segment::Shortcode(shortcode) => {
+                events.push(Event::Html(CowStr::from(shortcode.to_placeholder())))
+            }
+        }
+    }
+    text.clear();
+    html_spans.clear();
+}
+
+pub(crate) fn expand_djot_events<'s>(
+    events: impl Iterator<Item = jotdown::Event<'s>>,
+) -> impl Iterator<Item = jotdown::Event<'s>> {
+    let mut expanded: Vec<jotdown::Event<'s>> = Vec::new();
+    let mut text = String::new();
+    // the events `text` was joined from, kept as they are when it holds no
+    // shortcode
+    let mut joined: Vec<jotdown::Event<'s>> = Vec::new();
+    let mut code_depth = 0;
+
+    for event in events {
+        if code_depth == 0 {
+            if let Some(t) = djot_text(&event) {
+                text.push_str(t);
+                joined.push(event);
+                continue;
+            }
+        }
+        flush_djot_text(&mut text, &mut joined, &mut expanded);
+        match &event {
+            jotdown::Event::Start(container, _) if is_djot_code(container) => code_depth += 1,
+            jotdown::Event::End(container) if is_djot_code(container) => code_depth -= 1,
+            _ => {}
+        }
+        expanded.push(event);
+    }
+    flush_djot_text(&mut text, &mut joined, &mut expanded);
+
+    // a shortcode alone in a paragraph stands in for the whole block
+    let mut unwrapped: Vec<jotdown::Event<'s>> = Vec::with_capacity(expanded.len());
+    let mut rest = &expanded[..];
+    while let [event, remaining @ ..] = rest {
+        match rest {
+            [jotdown::Event::Start(jotdown::Container::Paragraph, _), jotdown::Event::Start(jotdown::Container::RawInline { .. }, _), jotdown::Event::Str(html), jotdown::Event::End(jotdown::Container::RawInline { .. }), jotdown::Event::End(jotdown::Container::Paragraph), ..]
+                if is_placeholder(html) =>
+            {
+                let format = "html";
+                unwrapped.push(jotdown::Event::Start(
+                    jotdown::Container::RawBlock { format },
+                    jotdown::Attributes::new(),
+                ));
+                unwrapped.push(jotdown::Event::Str(html.clone()));
+                unwrapped.push(jotdown::Event::End(jotdown::Container::RawBlock { format }));
+                rest = &rest[5..];
+            }
+            _ => {
+                unwrapped.push(event.clone());
+                rest = remaining;
+            }
+        }
+    }
+
+    unwrapped.into_iter()
+}
+
+fn flush_djot_text<'s>(
+    text: &mut String,
+    joined: &mut Vec<jotdown::Event<'s>>,
+    events: &mut Vec<jotdown::Event<'s>>,
+) {
+    let text = std::mem::take(text);
+    let segments = split_shortcodes(&text);
+    if segments
+        .iter()
+        .all(|segment| matches!(segment, Segment::Text(_)))
+    {
+        events.append(joined);
+        return;
+    }
+    for segment in segments {
+        match segment {
+            Segment::Text(t) => events.push(jotdown::Event::Str(String::from(t).into())),
+            Segment::Shortcode(shortcode) => {
+                events.extend(djot_raw_html(shortcode.to_placeholder()))
+            }
+        }
+    }
+    joined.clear();
+}
+
+/// Expands shortcodes in raw source text, so they are left alone by a later
+/// template pass.
+pub(crate) fn expand_text(text: &str) -> String {
+    let mut expanded = String::new();
+    for segment in split_shortcodes(text) {
+        match segment {
+            Segment::Text(t) => expanded.push_str(t),
+            Segment::Shortcode(shortcode) => expanded.push_str(&shortcode.to_placeholder()),
+        }
+    }
+    expanded
+}
+
+pub(super) enum Segment<'a> {
+    Text(&'a str),
+    Shortcode(Shortcode),
+}
+
+pub(super) fn split_shortcodes(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(SHORTCODE_OPEN) {
+        let inner = &rest[start + SHORTCODE_OPEN.len()..];
+        let shortcode = match inner.find(SHORTCODE_CLOSE) {
+            Some(end) => inner[..end].parse::<Shortcode>().ok().map(|s| (s, end)),
+            None => None,
+        };
+        let (shortcode, end) = match shortcode {
+            Some(shortcode) => shortcode,
+            None => {
+                segments.push(Segment::Text(&rest[..start + SHORTCODE_OPEN.len()]));
+                rest = inner;
+                continue;
+            }
+        };
+        if start > 0 {
+            segments.push(Segment::Text(&rest[..start]));
+        }
+        segments.push(Segment::Shortcode(shortcode));
+        rest = &inner[end + SHORTCODE_CLOSE.len()..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    segments
+}
+
+fn is_placeholder(html: &str) -> bool {
+    html.starts_with(PLACEHOLDER_OPEN) && html.ends_with(PLACEHOLDER_CLOSE)
+}
+
+impl Shortcode {
+    fn to_placeholder(&self) -> String {
+        let args = Value::Object(self.args.clone()).to_string();
+        format!(
+            "{}{}:{}{}",
+            PLACEHOLDER_OPEN,
+            self.name,
+            Base64Url::encode_string(args.as_bytes()),
+            PLACEHOLDER_CLOSE
+        )
+    }
+
+    fn from_placeholder(s: &str) -> Option<Shortcode> {
+        let (name, args) = s.split_once(':')?;
+        let args = Base64Url::decode_vec(args).ok()?;
+        let args = serde_json::from_slice::<Map<String, Value>>(&args).ok()?;
+        Some(Shortcode {
+            name: String::from(name),
+            args,
+        })
+    }
+}
+impl std::str::FromStr for Shortcode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, mut rest) = match s.split_once(char::is_whitespace) {
+            Some((name, rest)) => (name, rest.trim_start()),
+            None => (s, ""),
+        };
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_alphanumeric() || "-_/".contains(c))
+        {
+            return Err(format!("'{}' is not a valid shortcode name", name));
+        }
+
+        let mut args = Map::new();
+        while !rest.is_empty() {
+            let (key, value) = rest
+                .split_once('=')
+                .ok_or_else(|| format!("Expected 'key=value' argument, found '{}'", rest))?;
+            let key = key.trim();
+            let value = value.trim_start();
+            let (value, remainder) = match value.strip_prefix('"') {
+                Some(quoted) => quoted
+                    .split_once('"')
+                    .ok_or_else(|| format!("Unterminated string for argument '{}'", key))?,
+                None => value.split_once(char::is_whitespace).unwrap_or((value, "")),
+            };
+            args.insert(String::from(key), Value::String(String::from(value)));
+            rest = remainder.trim_start();
+        }
+
+        Ok(Shortcode {
+            name: String::from(name),
+            args,
+        })
+    }
+}
+
+//
+// Render-time expansion
+
+/// Replaces every shortcode placeholder in `html` with the output of
+/// `render`, which is given the parsed shortcode.
+pub(crate) fn expand_shortcodes<E>(
+    html: &str,
+    mut render: impl FnMut(&Shortcode) -> Result<String, E>,
+    malformed: impl Fn(&str) -> E,
+) -> Result<String, E> {
+    let mut expanded = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find(PLACEHOLDER_OPEN) {
+        expanded.push_str(&rest[..start]);
+        let inner = &rest[start + PLACEHOLDER_OPEN.len()..];
+        let end = inner
+            .find(PLACEHOLDER_CLOSE)
+            .ok_or_else(|| malformed(inner))?;
+        let shortcode =
+            Shortcode::from_placeholder(&inner[..end]).ok_or_else(|| malformed(inner))?;
+        expanded.push_str(&render(&shortcode)?);
+        rest = &inner[end + PLACEHOLDER_CLOSE.len()..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_markdown_events, expand_shortcodes, Shortcode};
+
+    #[test]
+    fn test_shortcode_parses_arguments() {
+        let shortcode: Shortcode = r#" figure src="a b.png" width=300 "#.parse().unwrap();
+        assert_eq!(shortcode.name, "figure");
+        assert_eq!(shortcode.args["src"].as_str(), Some("a b.png"));
+        assert_eq!(shortcode.args["width"].as_str(), Some("300"));
+    }
+
+    #[test]
+    fn test_shortcode_expands_outside_code() {
+        let markdown = "{{< figure src=\"a.png\" >}}\n\n`{{< figure >}}`\n\n\
+            {{<figure src=\"b.png\">}} and {{<b>bold</b>}}\n";
+        let mut html = String::new();
+        let parser = pulldown_cmark::Parser::new(markdown);
+        pulldown_cmark::html::push_html(&mut html, expand_markdown_events(parser));
+
+        let expanded = expand_shortcodes(
+            &html,
+            |shortcode| {
+                let src = shortcode.args["src"].as_str().unwrap_or_default();
+                Ok::<_, ()>(format!("<figure>{}</figure>", src))
+            },
+            |_| (),
+        )
+        .unwrap();
+        assert!(expanded.starts_with("<figure>a.png</figure>"));
+        assert!(expanded.contains("<code>{{&lt; figure &gt;}}</code>"));
+        assert!(expanded.contains("<p><figure>b.png</figure> and {{<b>bold</b>}}</p>"));
+    }
+}
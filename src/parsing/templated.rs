@@ -0,0 +1,121 @@
+use super::{shortcodes, DjotString, HtmlString, MarkdownString, SamString, TextString};
+use base64ct::{Base64Url, Encoding};
+
+static PLACEHOLDER_OPEN: &str = "<!--template:";
+static PLACEHOLDER_CLOSE: &str = "-->";
+
+/// The conversion a templated block still needs once it has been rendered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TemplatedSource {
+    Markdown,
+    Sam,
+    Djot,
+    Text,
+    Verbatim,
+}
+impl TemplatedSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TemplatedSource::Markdown => "markdown",
+            TemplatedSource::Sam => "sam",
+            TemplatedSource::Djot => "djot",
+            TemplatedSource::Text => "text",
+            TemplatedSource::Verbatim => "verbatim",
+        }
+    }
+}
+impl std::str::FromStr for TemplatedSource {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "markdown" => Ok(TemplatedSource::Markdown),
+            "sam" => Ok(TemplatedSource::Sam),
+            "djot" => Ok(TemplatedSource::Djot),
+            "text" => Ok(TemplatedSource::Text),
+            "verbatim" => Ok(TemplatedSource::Verbatim),
+            _ => Err(format!("'{}' is not a valid templated source", s)),
+        }
+    }
+}
+
+/// Defers a block that opted into templating (`!template`) until render time.
+///
+/// Its raw source is carried in an html comment placeholder, so it is only
+/// ever seen by the template engine through `render_templated_blocks`.
+pub(crate) fn placeholder(source: TemplatedSource, content: &str) -> String {
+    format!(
+        "{}{}:{}{}",
+        PLACEHOLDER_OPEN,
+        source.as_str(),
+        Base64Url::encode_string(content.as_bytes()),
+        PLACEHOLDER_CLOSE
+    )
+}
+
+/// Replaces every templated block placeholder in `html` with its source,
+/// rendered by `render` and then converted like any other block. Shortcodes
+/// are set aside first, since their syntax isn't valid for the template engine.
+pub(crate) fn render_templated_blocks<E>(
+    html: &str,
+    mut render: impl FnMut(&str) -> Result<String, E>,
+    malformed: impl Fn(&str) -> E,
+) -> Result<String, E> {
+    let mut rendered = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find(PLACEHOLDER_OPEN) {
+        rendered.push_str(&rest[..start]);
+        let inner = &rest[start + PLACEHOLDER_OPEN.len()..];
+        let end = inner
+            .find(PLACEHOLDER_CLOSE)
+            .ok_or_else(|| malformed(inner))?;
+        let (source, content) = decode(&inner[..end]).ok_or_else(|| malformed(inner))?;
+        let content = render(&shortcodes::expand_text(&content))?;
+        let content = match source {
+            TemplatedSource::Markdown => HtmlString::from(MarkdownString { content }).content,
+            TemplatedSource::Sam => HtmlString::from(SamString { content }).content,
+            TemplatedSource::Djot => HtmlString::from(DjotString { content }).content,
+            TemplatedSource::Text => HtmlString::from(TextString { content }).content,
+            TemplatedSource::Verbatim => content,
+        };
+        rendered.push_str(&content);
+        rest = &inner[end + PLACEHOLDER_CLOSE.len()..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+/// `html` with every templated block converted from its unrendered source, for
+/// finding what a block links to before it can be rendered.
+pub(crate) fn convert_unrendered(html: &str) -> String {
+    render_templated_blocks(html, |source| Ok::<_, ()>(String::from(source)), |_| ())
+        .unwrap_or_else(|_| String::from(html))
+}
+
+fn decode(s: &str) -> Option<(TemplatedSource, String)> {
+    let (source, content) = s.split_once(':')?;
+    let source = source.parse().ok()?;
+    let content = Base64Url::decode_vec(content).ok()?;
+    let content = String::from_utf8(content).ok()?;
+    Some((source, content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{placeholder, render_templated_blocks, TemplatedSource};
+
+    #[test]
+    fn test_only_templated_blocks_are_rendered() {
+        let html = format!(
+            "<p>{{{{ untouched }}}}</p>{}",
+            placeholder(TemplatedSource::Markdown, "# {{ title }}")
+        );
+        let rendered = render_templated_blocks(
+            &html,
+            |source| Ok::<_, ()>(source.replace("{{ title }}", "Hello")),
+            |_| (),
+        )
+        .unwrap();
+        assert_eq!(rendered, "<p>{{ untouched }}</p><h1>Hello</h1>\n");
+    }
+}
@@ -0,0 +1,308 @@
+use super::{djot_raw_html, djot_text, is_djot_code, templated};
+use pulldown_cmark::{escape::escape_html, CowStr, Event, Tag};
+
+static LINK_OPEN: &str = "<a class=\"wikilink\" href=\"wikilink:";
+
+//
+// Parse-time expansion
+//
+// `[[content-name]]` and `[[content-name|label]]` become placeholder anchors
+// that the renderer resolves once every content url is known. A placeholder
+// with an empty label is given the target's title at resolution.
+
+pub(crate) fn expand_markdown_events<'a>(
+    events: impl Iterator<Item = Event<'a>>,
+) -> impl Iterator<Item = Event<'a>> {
+    let mut expanded: Vec<Event<'a>> = Vec::new();
+    let mut text = String::new();
+    let mut in_code_block = false;
+
+    for event in events {
+        match event {
+            Event::Text(t) if !in_code_block => {
+                text.push_str(&t);
+                continue;
+            }
+            _ => flush_markdown_text(&mut text, &mut expanded),
+        }
+        match &event {
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(Tag::CodeBlock(_)) => in_code_block = false,
+            _ => {}
+        }
+        expanded.push(event);
+    }
+    flush_markdown_text(&mut text, &mut expanded);
+
+    expanded.into_iter()
+}
+
+fn flush_markdown_text<'a>(text: &mut String, events: &mut Vec<Event<'a>>) {
+    if text.is_empty() {
+        return;
+    }
+    for segment in split_wikilinks(text) {
+        match segment {
+            Segment::Text(t) => events.push(Event::Text(CowStr::from(String::from(t)))),
+            Segment::Link { name, label } => {
+                let mut escaped_label = String::new();
+                if let Some(label) = label {
+                    // writing to a String can't fail
+                    escape_html(&mut escaped_label, label).unwrap();
+                }
+                events.push(Event::Html(CowStr::from(placeholder(name, &escaped_label))));
+            }
+        }
+    }
+    text.clear();
+}
+
+pub(crate) fn expand_djot_events<'s>(
+    events: impl Iterator<Item = jotdown::Event<'s>>,
+) -> impl Iterator<Item = jotdown::Event<'s>> {
+    let mut expanded: Vec<jotdown::Event<'s>> = Vec::new();
+    let mut text = String::new();
+    // the events `text` was joined from, kept as they are when it holds no
+    // wikilink
+    let mut joined: Vec<jotdown::Event<'s>> = Vec::new();
+    let mut code_depth = 0;
+
+    for event in events {
+        if code_depth == 0 {
+            if let Some(t) = djot_text(&event) {
+                text.push_str(t);
+                joined.push(event);
+                continue;
+            }
+        }
+        flush_djot_text(&mut text, &mut joined, &mut expanded);
+        match &event {
+            jotdown::Event::Start(container, _) if is_djot_code(container) => code_depth += 1,
+            jotdown::Event::End(container) if is_djot_code(container) => code_depth -= 1,
+            _ => {}
+        }
+        expanded.push(event);
+    }
+    flush_djot_text(&mut text, &mut joined, &mut expanded);
+
+    expanded.into_iter()
+}
+
+fn flush_djot_text<'s>(
+    text: &mut String,
+    joined: &mut Vec<jotdown::Event<'s>>,
+    events: &mut Vec<jotdown::Event<'s>>,
+) {
+    let text = std::mem::take(text);
+    let segments = split_wikilinks(&text);
+    if segments
+        .iter()
+        .all(|segment| matches!(segment, Segment::Text(_)))
+    {
+        events.append(joined);
+        return;
+    }
+    for segment in segments {
+        match segment {
+            Segment::Text(t) => events.push(jotdown::Event::Str(String::from(t).into())),
+            Segment::Link { name, label } => {
+                let mut escaped_label = String::new();
+                if let Some(label) = label {
+                    // writing to a String can't fail
+                    escape_html(&mut escaped_label, label).unwrap();
+                }
+                events.extend(djot_raw_html(placeholder(name, &escaped_label)));
+            }
+        }
+    }
+    joined.clear();
+}
+
+/// Expands wikilinks in text that is already html, so labels are not escaped.
+pub(crate) fn expand_html(html: &str) -> String {
+    let mut expanded = String::new();
+    for segment in split_wikilinks(html) {
+        match segment {
+            Segment::Text(t) => expanded.push_str(t),
+            Segment::Link { name, label } => {
+                expanded.push_str(&placeholder(name, label.unwrap_or("")))
+            }
+        }
+    }
+    expanded
+}
+
+fn placeholder(name: &str, label: &str) -> String {
+    format!("{}{}\">{}</a>", LINK_OPEN, name, label)
+}
+
+pub(super) enum Segment<'a> {
+    Text(&'a str),
+    Link {
+        name: &'a str,
+        label: Option<&'a str>,
+    },
+}
+
+pub(super) fn split_wikilinks(s: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("[[") {
+        let inner = &rest[start + 2..];
+        let end = match inner.find("]]") {
+            Some(end) => end,
+            None => break,
+        };
+        let (name, label) = match inner[..end].split_once('|') {
+            Some((name, label)) => (name.trim(), Some(label.trim())),
+            None => (inner[..end].trim(), None),
+        };
+        if !is_valid_name(name) {
+            segments.push(Segment::Text(&rest[..start + 2]));
+            rest = inner;
+            continue;
+        }
+        if start > 0 {
+            segments.push(Segment::Text(&rest[..start]));
+        }
+        segments.push(Segment::Link { name, label });
+        rest = &inner[end + 2..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    segments
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '<' | '>' | '&' | '[' | ']'))
+}
+
+//
+// Render-time resolution
+
+/// Every content name referenced by a wikilink in `html`, including those in
+/// templated blocks that are still waiting to be rendered.
+pub(crate) fn collect_wikilinks(html: &str) -> Vec<String> {
+    let html = templated::convert_unrendered(html);
+    let mut names = Vec::new();
+    let mut rest = html.as_str();
+    while let Some(start) = rest.find(LINK_OPEN) {
+        rest = &rest[start + LINK_OPEN.len()..];
+        if let Some(end) = rest.find('"') {
+            names.push(String::from(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    names
+}
+
+/// The target a hoisted gemtext link line points at before it is resolved.
+pub(super) fn gemtext_target(name: &str) -> String {
+    format!("wikilink:{}", name)
+}
+
+/// Resolves `=> wikilink:name` lines in gemtext. `resolve` maps a content
+/// name to its `(url, title)`, and the title stands in for a missing label.
+pub(crate) fn resolve_gemtext_wikilinks<E>(
+    gemtext: &str,
+    mut resolve: impl FnMut(&str) -> Result<(String, String), E>,
+) -> Result<String, E> {
+    let mut resolved = String::new();
+    for line in gemtext.split_inclusive('\n') {
+        let link = match line.strip_prefix("=> wikilink:") {
+            Some(link) => link.trim_end(),
+            None => {
+                resolved.push_str(line);
+                continue;
+            }
+        };
+        let (name, label) = match link.split_once(' ') {
+            Some((name, label)) => (name, Some(label)),
+            None => (link, None),
+        };
+        let (url, title) = resolve(name)?;
+        resolved.push_str(&format!("=> {} {}", url, label.unwrap_or(&title)));
+        if line.ends_with('\n') {
+            resolved.push('\n');
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Replaces every wikilink placeholder with a real link. `resolve` maps a
+/// content name to its `(url, title)`.
+pub(crate) fn resolve_wikilinks<E>(
+    html: &str,
+    mut resolve: impl FnMut(&str) -> Result<(String, String), E>,
+) -> Result<String, E> {
+    let mut resolved = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find(LINK_OPEN) {
+        resolved.push_str(&rest[..start]);
+        let link = &rest[start + LINK_OPEN.len()..];
+        let (name, label, remainder) = match link.split_once("\">") {
+            Some((name, after)) => match after.split_once("</a>") {
+                Some((label, remainder)) => (name, label, remainder),
+                None => (name, "", after),
+            },
+            None => (link, "", ""),
+        };
+        let (url, title) = resolve(name)?;
+        let label = match label {
+            "" => {
+                let mut escaped = String::new();
+                escape_html(&mut escaped, &title).unwrap();
+                escaped
+            }
+            label => String::from(label),
+        };
+        resolved.push_str(&format!(
+            "<a class=\"wikilink\" href=\"{}\">{}</a>",
+            url, label
+        ));
+        rest = remainder;
+    }
+    resolved.push_str(rest);
+
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collect_wikilinks, expand_markdown_events, resolve_wikilinks};
+    use crate::parsing::templated::{placeholder, TemplatedSource};
+
+    fn markdown_to_html(markdown: &str) -> String {
+        let mut html = String::new();
+        let parser = pulldown_cmark::Parser::new(markdown);
+        pulldown_cmark::html::push_html(&mut html, expand_markdown_events(parser));
+        html
+    }
+
+    #[test]
+    fn test_wikilinks_expand_outside_code() {
+        let html =
+            markdown_to_html("see [[notes/garden]] and [[about|me]]\n\n    [[not-a-link]]\n");
+        assert_eq!(collect_wikilinks(&html), vec!["notes/garden", "about"]);
+        assert!(html.contains("[[not-a-link]]"));
+
+        let templated = placeholder(TemplatedSource::Markdown, "{{ title }} [[garden]]");
+        assert_eq!(collect_wikilinks(&templated), vec!["garden"]);
+    }
+
+    #[test]
+    fn test_wikilinks_resolve_with_title_fallback() {
+        let html = markdown_to_html("[[garden]] / [[about|me]]");
+        let resolved = resolve_wikilinks(&html, |name| {
+            Ok::<_, ()>((format!("../{}.html", name), format!("The {}", name)))
+        })
+        .unwrap();
+        assert!(resolved.contains("<a class=\"wikilink\" href=\"../garden.html\">The garden</a>"));
+        assert!(resolved.contains("<a class=\"wikilink\" href=\"../about.html\">me</a>"));
+    }
+}
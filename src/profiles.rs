@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Profile '{name}' isn't defined in config.json")]
+    UnknownProfileError { name: String },
+
+    #[error("Profiles in config.json were malformed")]
+    MalformedProfilesError { source: serde_json::Error },
+}
+
+pub(crate) static PROFILE_ENV_VAR: &str = "SITE_BUILDER_PROFILE";
+pub(crate) static DEFAULT_PROFILE: &str = "dev";
+
+/// A named entry in the `profiles` section of config.json.
+#[derive(Deserialize, Clone, Debug, Default)]
+struct ProfileConfig {
+    context: Option<Value>,
+    drafts: Option<bool>,
+    minify: Option<bool>,
+    fingerprint: Option<bool>,
+}
+
+/// The selected build profile, exposed to templates as `build`.
+#[derive(Serialize, Clone, Debug)]
+pub(crate) struct BuildProfile {
+    pub profile: String,
+    pub drafts: bool,
+    pub minify: bool,
+    pub fingerprint: bool,
+}
+
+/// Takes the `profiles` section out of a raw config and overlays the
+/// `context` of profile `name` onto the rest of it.
+///
+/// The default profile doesn't need to be defined; any other does.
+pub(crate) fn apply_profile(config: &mut Value, name: &str) -> Result<BuildProfile, Error> {
+    let profiles = match config.as_object_mut().and_then(|c| c.remove("profiles")) {
+        Some(profiles) => serde_json::from_value::<HashMap<String, ProfileConfig>>(profiles)
+            .map_err(|e| Error::MalformedProfilesError { source: e })?,
+        None => HashMap::new(),
+    };
+    let profile = match profiles.get(name) {
+        Some(profile) => profile.clone(),
+        None if name == DEFAULT_PROFILE => ProfileConfig::default(),
+        None => {
+            return Err(Error::UnknownProfileError {
+                name: String::from(name),
+            })
+        }
+    };
+
+    if let Some(overlay) = profile.context {
+        merge(config, overlay);
+    }
+
+    Ok(BuildProfile {
+        profile: String::from(name),
+        drafts: profile.drafts.unwrap_or(false),
+        minify: profile.minify.unwrap_or(false),
+        fingerprint: profile.fingerprint.unwrap_or(false),
+    })
+}
+
+/// Merges objects key by key; anything else in `overlay` replaces `base`.
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::apply_profile;
+    use serde_json::json;
+
+    #[test]
+    fn test_profile_overlays_context() {
+        let mut config = json!({
+            "site_title": "Site",
+            "analytics": { "enabled": false, "id": "x" },
+            "profiles": {
+                "prod": {
+                    "context": { "site_url": "https://example.com", "analytics": { "enabled": true } },
+                    "minify": true
+                }
+            }
+        });
+        let profile = apply_profile(&mut config, "prod").unwrap();
+        assert!(profile.minify && !profile.drafts);
+        assert_eq!(
+            config,
+            json!({
+                "site_title": "Site",
+                "site_url": "https://example.com",
+                "analytics": { "enabled": true, "id": "x" }
+            })
+        );
+    }
+
+    #[test]
+    fn test_undefined_profiles() {
+        let mut config = json!({ "site_title": "Site" });
+        assert!(apply_profile(&mut config, "dev").is_ok());
+        assert!(apply_profile(&mut config, "prod").is_err());
+    }
+}
@@ -2,10 +2,18 @@ use crate::files::{
     ensure_directory, get_relative_path_string, load_component_files, write_file_contents,
     Error as FilesError,
 };
+use crate::images::{self, resolve_gemtext_images, ImageReference, ProcessedImages};
+use crate::minify::{self, minify_html};
+use crate::navigation::{matches_content_name, Navigation, NavigationLink};
+use crate::parsing::{
+    expand_shortcodes, render_templated_blocks, resolve_gemtext_wikilinks, resolve_wikilinks,
+    Entry, Shortcode,
+};
 use crate::{BuildConfig, SiteConfig};
 use base64ct::{Base64Url, Encoding};
 use blake2s_simd::Params;
 use serde::Serialize;
+use std::collections::HashMap;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -30,12 +38,43 @@ pub(crate) enum Error {
 
     #[error("Template engine error during render")]
     RenderError { source: tera::Error },
+
+    #[error("Templated block placeholder was malformed: '{placeholder}'")]
+    MalformedTemplatedBlockError { placeholder: String },
+
+    #[error("Shortcode '{name}' doesn't match any component")]
+    UnknownShortcodeError { name: String },
+
+    #[error("Shortcode placeholder was malformed: '{placeholder}'")]
+    MalformedShortcodeError { placeholder: String },
+
+    #[error("Template engine error while rendering shortcode '{name}'")]
+    ShortcodeRenderError { source: tera::Error, name: String },
+
+    #[error("Image '{src}' doesn't exist under static/images")]
+    MissingImageError { src: String },
+
+    #[error("Image placeholder was malformed: '{placeholder}'")]
+    MalformedImageError { placeholder: String },
+
+    #[error("Couldn't copy image '{path}' into the capsule")]
+    CapsuleImageCopyError {
+        source: std::io::Error,
+        path: String,
+    },
+
+    #[error("Wikilink target '{name}' doesn't exist")]
+    MissingWikilinkTargetError { name: String },
+
+    #[error("Wikilink target '{name}' has no registered url")]
+    UnregisteredWikilinkTargetError { name: String },
 }
 
 pub(crate) struct Renderer<'a> {
     pub template_engine: tera::Tera,
     pub base_context: tera::Context,
     pub build_config: &'a BuildConfig,
+    pub images: ProcessedImages,
 }
 
 #[derive(Clone)]
@@ -45,11 +84,28 @@ pub(crate) enum RenderDestination {
     Permalink { directory: String },
 }
 
-pub(crate) struct RenderPassDescriptor<T: Serialize> {
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum RenderFormat {
+    Html,
+    Gemtext,
+}
+impl RenderFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            RenderFormat::Html => "html",
+            RenderFormat::Gemtext => "gmi",
+        }
+    }
+}
+
+pub(crate) struct RenderPassDescriptor<'a, T: Serialize> {
     pub render_name: String,
-    pub base_template: &'static str,
+    pub base_template: &'a str,
+    pub format: RenderFormat,
     pub destination: RenderDestination,
     pub context: T,
+    pub navigation: Option<Navigation>,
+    pub backlinks: Vec<NavigationLink>,
 }
 
 pub(crate) struct Export {
@@ -61,6 +117,8 @@ impl<'a> Renderer<'a> {
     pub(crate) fn new(
         build_config: &'a BuildConfig,
         site_config: &'a SiteConfig,
+        images: ProcessedImages,
+        assets: HashMap<String, String>,
     ) -> Result<Renderer<'a>, Error> {
         let log = build_config.debug;
 
@@ -96,6 +154,22 @@ impl<'a> Renderer<'a> {
             },
         );
 
+        // function for image(src, alt, sizes) -> responsive <picture>
+        template_engine.register_function(
+            "image",
+            |args: &std::collections::HashMap<String, serde_json::Value>|
+             -> Result<serde_json::Value, tera::Error> {
+                let arg = |name: &str| args.get(name).and_then(|v| v.as_str()).map(String::from);
+                let src = arg("src").ok_or_else(|| tera::Error::msg("image() requires 'src'"))?;
+                let reference = ImageReference {
+                    src: String::from(src.trim_start_matches('/').trim_start_matches("images/")),
+                    alt: arg("alt").unwrap_or_default(),
+                    sizes: arg("sizes"),
+                };
+                Ok(serde_json::Value::String(images::placeholder(&reference)))
+            },
+        );
+
         let comp_glob = &build_config.components_glob;
         if log {
             println!("Loading components from '{}'\n", comp_glob);
@@ -117,6 +191,22 @@ impl<'a> Renderer<'a> {
             .map_err(|e| Error::CreateTeraContextError { source: e })?;
         base_context.insert("posts", &site_config.posts);
         base_context.insert("pages", &site_config.pages);
+        base_context.insert("data", &site_config.data);
+
+        // entries of user-defined types, grouped by type then content name
+        let mut collections: HashMap<&String, HashMap<&String, &Entry>> = HashMap::new();
+        for content_type in site_config.context.content_types.iter().flatten() {
+            collections.insert(&content_type.name, HashMap::new());
+        }
+        for (name, entry) in &site_config.entries {
+            collections
+                .entry(&entry.content_type)
+                .or_default()
+                .insert(name, entry);
+        }
+        base_context.insert("collections", &collections);
+        base_context.insert("build", &build_config.profile);
+        base_context.insert("assets", &assets);
 
         if log {
             println!("Loaded base context:");
@@ -130,17 +220,24 @@ impl<'a> Renderer<'a> {
             template_engine,
             base_context,
             build_config,
+            images,
         })
     }
 
     pub(crate) fn register_post_url(&mut self, name: &str, url: String) {
-        self.register_url("posts", name, url);
+        self.register_url("posts", name, "url", url);
     }
     pub(crate) fn register_page_url(&mut self, name: &str, url: String) {
-        self.register_url("pages", name, url);
+        self.register_url("pages", name, "url", url);
+    }
+    pub(crate) fn register_post_capsule_url(&mut self, name: &str, url: String) {
+        self.register_url("posts", name, "capsule_url", url);
+    }
+    pub(crate) fn register_page_capsule_url(&mut self, name: &str, url: String) {
+        self.register_url("pages", name, "capsule_url", url);
     }
 
-    fn register_url(&mut self, container_name: &str, name: &str, url: String) {
+    fn register_url(&mut self, container_name: &str, name: &str, key: &str, url: String) {
         let mut context = self.base_context.clone().into_json();
         let container = context
             .get_mut(container_name)
@@ -152,26 +249,214 @@ impl<'a> Renderer<'a> {
             .expect("uhhh")
             .as_object_mut()
             .expect("uhh");
-        element.insert(String::from("url"), serde_json::Value::String(url));
+        element.insert(String::from(key), serde_json::Value::String(url));
         self.base_context = tera::Context::from_value(context).expect("uhh");
     }
 
+    fn render_shortcode(
+        &self,
+        shortcode: &Shortcode,
+        context: &tera::Context,
+    ) -> Result<String, Error> {
+        let component_name = format!("components/{}", shortcode.name);
+        let template_name = self
+            .template_engine
+            .get_template_names()
+            .find(|name| {
+                *name == component_name
+                    || name
+                        .strip_prefix(&component_name)
+                        .is_some_and(|extension| extension.starts_with('.'))
+            })
+            .ok_or_else(|| Error::UnknownShortcodeError {
+                name: shortcode.name.clone(),
+            })?;
+
+        let mut context = context.clone();
+        context.insert("args", &shortcode.args);
+        self.template_engine
+            .render(template_name, &context)
+            .map_err(|e| Error::ShortcodeRenderError {
+                source: e,
+                name: shortcode.name.clone(),
+            })
+    }
+
+    fn resolve_image(&self, reference: &ImageReference, base_url: &str) -> Result<String, Error> {
+        let image =
+            self.images
+                .images
+                .get(&reference.src)
+                .ok_or_else(|| Error::MissingImageError {
+                    src: reference.src.clone(),
+                })?;
+        let sizes = reference.sizes.as_ref().unwrap_or(&self.images.sizes);
+        Ok(image.to_html(base_url, &reference.alt, sizes))
+    }
+
+    /// Copies the largest variant of an image into the capsule, and returns
+    /// its url from `base_url`.
+    fn copy_capsule_image(
+        &self,
+        src: &str,
+        capsule_dir: &str,
+        base_url: &str,
+    ) -> Result<String, Error> {
+        let image = self
+            .images
+            .images
+            .get(src)
+            .ok_or_else(|| Error::MissingImageError {
+                src: String::from(src),
+            })?;
+        // variants are never empty, there's always at least the source width
+        let url = &image.variants.last().unwrap().jpeg_url;
+        let from = format!("{}/{}", self.build_config.output_dir_path, url);
+        let to = format!("{}/{}", capsule_dir, url);
+        if let Some((directory, _)) = to.rsplit_once('/') {
+            ensure_directory(directory).map_err(|e| Error::WriteExportError {
+                source: e,
+                name: to.clone(),
+            })?;
+        }
+        std::fs::copy(&from, &to).map_err(|e| Error::CapsuleImageCopyError {
+            source: e,
+            path: from.clone(),
+        })?;
+
+        Ok(format!("{}/{}", base_url, url))
+    }
+
+    fn resolve_wikilink(
+        context: &serde_json::Value,
+        name: &str,
+        url_key: &str,
+        base_url: &str,
+    ) -> Result<(String, String), Error> {
+        // entries are grouped by type under `collections`
+        let collections = context
+            .get("collections")
+            .and_then(|collections| collections.as_object())
+            .into_iter()
+            .flat_map(|collections| collections.values());
+        let element = ["posts", "pages"]
+            .iter()
+            .filter_map(|container| context.get(container))
+            .chain(collections)
+            .filter_map(|container| container.as_object())
+            .find_map(|container| {
+                container.get(name).or_else(|| {
+                    container
+                        .iter()
+                        .find(|(key, _)| matches_content_name(key, name))
+                        .map(|(_, element)| element)
+                })
+            })
+            .ok_or_else(|| Error::MissingWikilinkTargetError {
+                name: String::from(name),
+            })?;
+        let url = element
+            .get(url_key)
+            .and_then(|url| url.as_str())
+            .ok_or_else(|| Error::UnregisteredWikilinkTargetError {
+                name: String::from(name),
+            })?;
+        let title = element
+            .get("title")
+            .and_then(|title| title.as_str())
+            .unwrap_or(name);
+
+        Ok((format!("{}/{}", base_url, url), String::from(title)))
+    }
+
+    /// Renders a content fragment on its own (outside of any template),
+    /// resolving its placeholders against `base_url`.
+    pub(crate) fn render_fragment<T: Serialize>(
+        &mut self,
+        html: &str,
+        render: &T,
+        base_url: &str,
+    ) -> Result<String, Error> {
+        let mut context = self.base_context.clone();
+        context.insert("base_url", base_url);
+        context.insert("render", render);
+        self.resolve_placeholders(html, &context, base_url)
+    }
+
+    fn resolve_placeholders(
+        &mut self,
+        output: &str,
+        context: &tera::Context,
+        base_url: &str,
+    ) -> Result<String, Error> {
+        // render blocks that opted into templating, and only those
+        let output = render_templated_blocks(
+            output,
+            |source| {
+                self.template_engine
+                    .render_str(source, context)
+                    .map_err(|e| Error::RenderError { source: e })
+            },
+            |placeholder| Error::MalformedTemplatedBlockError {
+                placeholder: String::from(placeholder),
+            },
+        )?;
+
+        // expand shortcodes into their components
+        let output = expand_shortcodes(
+            &output,
+            |shortcode| self.render_shortcode(shortcode, context),
+            |placeholder| Error::MalformedShortcodeError {
+                placeholder: String::from(placeholder),
+            },
+        )?;
+
+        // resolve images into responsive markup
+        let output = images::resolve_images(
+            &output,
+            |reference| self.resolve_image(reference, base_url),
+            |placeholder| Error::MalformedImageError {
+                placeholder: String::from(placeholder),
+            },
+        )?;
+
+        // resolve wikilinks against registered urls
+        let content = self.base_context.clone().into_json();
+        let output = resolve_wikilinks(&output, |name| {
+            Renderer::resolve_wikilink(&content, name, "url", base_url)
+        })?;
+
+        Ok(output)
+    }
+
     pub(crate) fn render_content<T: Serialize>(
         &mut self,
-        desc: RenderPassDescriptor<T>,
+        desc: RenderPassDescriptor<'_, T>,
     ) -> Result<Export, Error> {
         let destination = match &desc.destination {
             RenderDestination::SectionIndex { directory } => directory,
             RenderDestination::Permalink { directory } => directory,
             RenderDestination::Explicit { directory, .. } => directory,
         };
-        let base_url = get_relative_path_string(&self.build_config.output_dir_path, destination)
+        let root = match desc.format {
+            RenderFormat::Html => &self.build_config.output_dir_path,
+            RenderFormat::Gemtext => self
+                .build_config
+                .capsule_dir_path
+                .as_ref()
+                .unwrap_or(&self.build_config.output_dir_path),
+        };
+        let base_url = get_relative_path_string(root, destination)
             .map_err(|e| Error::AmbiguousDestinationError { source: e })?;
 
         // create context for render
         let mut context = self.base_context.clone();
         context.insert("base_url", &base_url);
         context.insert("render", &desc.context);
+        if let Some(navigation) = &desc.navigation {
+            context.insert("navigation", navigation);
+        }
+        context.insert("backlinks", &desc.backlinks);
 
         // render
         print!("rendering '{}'...", &desc.render_name);
@@ -180,16 +465,37 @@ impl<'a> Renderer<'a> {
             .render(&desc.base_template, &context)
             .map_err(|e| Error::RenderError { source: e })?;
 
-        // 2nd render to resolve component includes
-        let output = self
-            .template_engine
-            .render_str(&output, &context)
-            .map_err(|e| Error::RenderError { source: e })?;
+        // gemtext only carries wikilinks and images, hoisted to link lines
+        if desc.format == RenderFormat::Gemtext {
+            let content = self.base_context.clone().into_json();
+            let output = resolve_gemtext_wikilinks(&output, |name| {
+                Renderer::resolve_wikilink(&content, name, "capsule_url", &base_url)
+            })?;
+            let output = resolve_gemtext_images(&output, |src| {
+                self.copy_capsule_image(src, root, &base_url)
+            })?;
+            print!("ok\n");
+            return export(&desc.render_name, &output, desc.destination, desc.format);
+        }
+
+        let output = self.resolve_placeholders(&output, &context, &base_url)?;
 
         print!("ok\n");
 
+        // minify
+        let output = match self.build_config.profile.minify {
+            true => {
+                let minified = minify_html(&output);
+                if self.build_config.debug {
+                    minify::report(&desc.render_name, output.len(), minified.len());
+                }
+                minified
+            }
+            false => output,
+        };
+
         // export
-        let export = export(&desc.render_name, &output, desc.destination)?;
+        let export = export(&desc.render_name, &output, desc.destination, desc.format)?;
 
         Ok(export)
     }
@@ -199,19 +505,23 @@ fn export(
     name: &String,
     content: &String,
     destination: RenderDestination,
+    format: RenderFormat,
 ) -> Result<Export, Error> {
+    let extension = format.extension();
     let (filename, path) = match destination {
-        RenderDestination::SectionIndex { directory } => (String::from("index.html"), directory),
+        RenderDestination::SectionIndex { directory } => {
+            (format!("index.{}", extension), directory)
+        }
         RenderDestination::Permalink { directory } => {
             let hash = Params::new().hash_length(12).hash(&content.as_bytes());
             let hash_string = Base64Url::encode_string(hash.as_bytes());
-            let filename = format!("{}.html", hash_string);
+            let filename = format!("{}.{}", hash_string, extension);
             (filename, directory)
         }
         RenderDestination::Explicit {
             directory,
             filename,
-        } => (format!("{}.html", filename), directory),
+        } => (format!("{}.{}", filename, extension), directory),
     };
     ensure_directory(&path).map_err(|e| Error::WriteExportError {
         source: e,
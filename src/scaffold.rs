@@ -0,0 +1,261 @@
+use crate::feeds::civil_from_days;
+use crate::files::{
+    ensure_directory, read_file_contents, write_file_contents, Error as FilesError,
+};
+use crate::parsing::{parse_blocks_file, Error as ParsingError, SiteContentType};
+use serde_json::Value;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum Error {
+    #[error("Content type '{name}' isn't declared in config.json")]
+    UnknownContentTypeError { name: String },
+
+    #[error("No author for new content; pass --author or set 'author_name' in config.json")]
+    MissingAuthorError,
+
+    #[error("'{title}' doesn't make a usable slug")]
+    EmptySlugError { title: String },
+
+    #[error("Couldn't read archetype '{path}'")]
+    ArchetypeReadError { source: FilesError, path: String },
+
+    #[error("Couldn't render archetype for '{content_type}'")]
+    ArchetypeRenderError {
+        source: tera::Error,
+        content_type: String,
+    },
+
+    #[error("'{path}' already exists")]
+    ExistingContentError { path: String },
+
+    #[error("Couldn't write '{path}'")]
+    ContentWriteError { source: FilesError, path: String },
+
+    #[error("The archetype for '{content_type}' made an invalid block file")]
+    InvalidArchetypeError {
+        source: ParsingError,
+        content_type: String,
+    },
+}
+
+static POST_ARCHETYPE: &str = r#"type::post
+post.metadata:json
++++
+{
+  "author_name": {{ author_name | json_encode() }},
+  "published_date": {{ published_date | json_encode() }},
+{% if directory is defined %}  "directory": {{ directory | json_encode() }},
+{% endif %}  "version": {{ version }}
+}
++++
+
+post.title:json
++++
+{{ title | json_encode() }}
++++
+
+post.slug:json
++++
+{{ slug | json_encode() }}
++++
+
+post.content:markdown->html
++++
++++
+"#;
+
+static PAGE_ARCHETYPE: &str = r#"type::page
+page.metadata:json
++++
+{
+  "author_name": {{ author_name | json_encode() }},
+  "published_date": {{ published_date | json_encode() }},
+{% if directory is defined %}  "directory": {{ directory | json_encode() }},
+{% endif %}  "version": {{ version }}
+}
++++
+
+page.title:json
++++
+{{ title | json_encode() }}
++++
+
+page.content:markdown->html
++++
++++
+"#;
+
+/// What `site-builder new` was asked to create.
+pub(crate) struct NewContent<'a> {
+    pub content_type: &'a str,
+    pub title: &'a str,
+    pub directory: Option<&'a str>,
+    pub author: Option<&'a str>,
+}
+
+/// Writes a new block file to `{content_dir}/{directory}/{slug}.{type}`
+/// from the type's archetype, and returns its path. Without a `directory`,
+/// posts go in `posts/`, pages in `pages/`, and user-defined types in the
+/// directory they're rendered to.
+///
+/// Archetypes only see `directory` when it was given, so that a file's place
+/// under `content/` doesn't decide its url.
+///
+/// Archetypes are tera templates, read from `{archetypes_dir}/{type}.tmpl`
+/// when there is one. Otherwise posts and pages have a built-in archetype,
+/// and user-defined types get one with a placeholder for each required field.
+pub(crate) fn create_content(
+    new: &NewContent,
+    content_dir: &str,
+    archetypes_dir: &str,
+    content_types: &[SiteContentType],
+) -> Result<String, Error> {
+    let custom_type = match new.content_type {
+        "post" | "page" => None,
+        name => Some(
+            content_types
+                .iter()
+                .find(|content_type| content_type.name == name)
+                .ok_or_else(|| Error::UnknownContentTypeError {
+                    name: String::from(name),
+                })?,
+        ),
+    };
+    let author_name = new.author.ok_or(Error::MissingAuthorError)?;
+    let slug = slugify(new.title);
+    if slug.is_empty() {
+        return Err(Error::EmptySlugError {
+            title: String::from(new.title),
+        });
+    }
+    // only a directory given by the user also goes into the content, where it
+    // changes the url and which posts are navigated between
+    let directory = new.directory.map(|directory| directory.trim_matches('/'));
+    let source_directory = match (directory, custom_type) {
+        (Some(directory), _) => String::from(directory),
+        (None, Some(content_type)) => content_type.directory(),
+        (None, None) => format!("{}s", new.content_type),
+    };
+
+    let archetype_path = format!("{}/{}.tmpl", archetypes_dir, new.content_type);
+    let archetype = match (Path::new(&archetype_path).exists(), custom_type) {
+        (true, _) => {
+            read_file_contents(&archetype_path).map_err(|e| Error::ArchetypeReadError {
+                source: e,
+                path: archetype_path.clone(),
+            })?
+        }
+        (false, Some(content_type)) => custom_archetype(content_type),
+        (false, None) if new.content_type == "post" => String::from(POST_ARCHETYPE),
+        (false, None) => String::from(PAGE_ARCHETYPE),
+    };
+
+    let mut context = tera::Context::new();
+    context.insert("content_type", new.content_type);
+    context.insert("title", new.title);
+    context.insert("slug", &slug);
+    context.insert("published_date", &today());
+    context.insert("author_name", author_name);
+    context.insert("version", &1);
+    if let Some(directory) = directory {
+        context.insert("directory", directory);
+    }
+    let contents = tera::Tera::one_off(&archetype, &context, false).map_err(|e| {
+        Error::ArchetypeRenderError {
+            source: e,
+            content_type: String::from(new.content_type),
+        }
+    })?;
+
+    let output_dir = match source_directory.as_str() {
+        "" => String::from(content_dir),
+        directory => format!("{}/{}", content_dir, directory),
+    };
+    let path = format!("{}/{}.{}", output_dir, slug, new.content_type);
+    if Path::new(&path).exists() {
+        return Err(Error::ExistingContentError { path });
+    }
+    ensure_directory(&output_dir).map_err(|e| Error::ContentWriteError {
+        source: e,
+        path: output_dir.clone(),
+    })?;
+    write_file_contents(&contents, &path).map_err(|e| Error::ContentWriteError {
+        source: e,
+        path: path.clone(),
+    })?;
+
+    // an archetype that can't be built would only fail on the next build
+    if let Err(e) = parse_blocks_file(&path, content_types) {
+        let _ = std::fs::remove_file(&path);
+        return Err(Error::InvalidArchetypeError {
+            source: e,
+            content_type: String::from(new.content_type),
+        });
+    }
+
+    Ok(path)
+}
+
+/// A single json block with the type's `title` and `slug` filled in, and an
+/// empty value for every other required field.
+fn custom_archetype(content_type: &SiteContentType) -> String {
+    let mut fields = Vec::new();
+    let mut has_slug = false;
+    for field in content_type.fields.iter().flatten() {
+        let value = match field.name.as_str() {
+            name @ ("title" | "slug" | "author_name" | "published_date") => {
+                has_slug |= name == "slug";
+                format!("{{{{ {} | json_encode() }}}}", name)
+            }
+            "version" => String::from("{{ version }}"),
+            _ if !field.required.unwrap_or(true) => continue,
+            _ => field
+                .field_type
+                .map_or(Value::Null, |field_type| field_type.empty_value())
+                .to_string(),
+        };
+        fields.push(format!("  {}: {}", Value::from(field.name.as_str()), value));
+    }
+    if !has_slug {
+        fields.push(String::from("  \"slug\": {{ slug | json_encode() }}"));
+    }
+    format!(
+        "type::{name}\n{name}:json\n+++\n{{\n{fields}\n}}\n+++\n",
+        name = content_type.name,
+        fields = fields.join(",\n"),
+    )
+}
+
+/// Lowercases `title`, keeping letters and digits and joining the words
+/// between them with '-'.
+fn slugify(title: &str) -> String {
+    title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Today's date in UTC, as `YYYY-MM-DD`.
+fn today() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::slugify;
+
+    #[test]
+    fn test_slugs() {
+        assert_eq!(slugify("Hello, World: Part 2!"), "hello-world-part-2");
+    }
+}